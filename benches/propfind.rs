@@ -0,0 +1,92 @@
+//! PROPFIND latency on a synthetic library of 1000 shows of 10 seasons of 10 episodes, about
+//! 111k nodes.
+//!
+//! The crate only builds a binary, so the filesystem module is compiled into the benchmark
+//! along with a stand-in for the debrid metadata its files hold.
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use fake_file_system::{FakeFilesystem, File, Folder, Node};
+use std::hint::black_box;
+use std::path::{Path, PathBuf};
+use webdav_meta::xml::IntoXml;
+use webdav_meta::xml::elements::Multistatus;
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/fake_file_system.rs"]
+mod fake_file_system;
+
+mod debrid {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Default, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+    pub struct DebridFileMetadata {
+        pub(crate) account: String,
+        pub(crate) torrent_id: String,
+        pub(crate) file_id: i64,
+    }
+}
+
+fn folder(fs: &mut FakeFilesystem, path: &Path) {
+    let name = path.file_name().unwrap().to_string_lossy().to_string();
+    fs.add_node(path, Node::Folder(Folder { name }));
+}
+
+fn library() -> FakeFilesystem {
+    let mut fs = FakeFilesystem::new_with_root();
+    let root = PathBuf::from("/shows");
+    folder(&mut fs, &root);
+    for show in 0..1000 {
+        let show_path = root.join(format!("Show {show}"));
+        folder(&mut fs, &show_path);
+        for season in 1..=10 {
+            let season_path = show_path.join(format!("Season {season}"));
+            folder(&mut fs, &season_path);
+            for episode in 1..=10 {
+                let name = format!("Show {show} - s{season:02}e{episode:02}.mkv");
+                fs.add_node(
+                    &season_path.join(&name),
+                    Node::File(File {
+                        name,
+                        size: 1_000_000_000,
+                        download_details: debrid::DebridFileMetadata {
+                            account: String::new(),
+                            torrent_id: show.to_string(),
+                            file_id: season * 100 + episode,
+                        },
+                    }),
+                );
+            }
+        }
+    }
+    fs
+}
+
+/// What the PROPFIND handler does for a folder with `Depth: 1`.
+fn propfind(fs: &FakeFilesystem, path: &Path) -> usize {
+    let node = fs.read_node(path).unwrap();
+    let mut responses = fs
+        .read_dir(path)
+        .unwrap()
+        .into_iter()
+        .map(|(child_path, child)| child.to_propstat_response(&child_path).unwrap())
+        .collect::<Vec<_>>();
+    responses.push(node.to_propstat_response(path).unwrap());
+    let multistatus = Multistatus {
+        responsedescription: None,
+        response: responses,
+    };
+    multistatus.into_xml().unwrap().len()
+}
+
+fn bench_propfind(c: &mut Criterion) {
+    let fs = library();
+    c.bench_function("propfind shows root", |b| {
+        b.iter(|| propfind(&fs, black_box(Path::new("/shows"))))
+    });
+    c.bench_function("propfind season", |b| {
+        b.iter(|| propfind(&fs, black_box(Path::new("/shows/Show 500/Season 5"))))
+    });
+}
+
+criterion_group!(benches, bench_propfind);
+criterion_main!(benches);
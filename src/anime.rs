@@ -0,0 +1,277 @@
+use crate::debrid::{DebridFileMetadata, DebridTorrent};
+use crate::movies::video_extension;
+use crate::overrides::Overrides;
+use crate::quality::Quality;
+use crate::shows::{Show, ShowEpisode, group_episodes};
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// Matches fansub style names such as `[SubsPlease] Show - 1071 (1080p).mkv`,
+/// `[Erai-raws] Show S2 - 05v2 [1080p].mkv` or `[Group] Show - S02E05 [1080p].mkv`.
+static FANSUB_EPISODE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?x)
+        ^\[(?P<group>[^\]]+)\]\s*
+        (?P<title>.+?)
+        (?:\s+S(?P<season>\d{1,2}))?
+        \s+-\s+
+        (?:S(?P<season_long>\d{1,2})E)?
+        (?P<episode>\d{1,4})
+        (?:v(?P<version>\d+))?
+        (?:[\s.\[(]|$)",
+    )
+    .unwrap()
+});
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnimeEpisode {
+    pub group: String,
+    pub title: String,
+    /// Season given by the release, `None` when the release only uses absolute numbering.
+    pub season: Option<i32>,
+    pub episode: i32,
+    /// Revision of the release, `05v2` fixes a mistake of the first `05`.
+    pub version: Option<i32>,
+}
+
+impl AnimeEpisode {
+    /// Absolute numbered releases all go into a flat `Season 1`, which is what Plex's absolute
+    /// order agent expects.
+    pub fn season_number(&self) -> i32 {
+        self.season.unwrap_or(1)
+    }
+}
+
+/// Detects a fansub style release name and extracts its episode numbering.
+pub fn parse_anime_episode(file_name: &str) -> Option<AnimeEpisode> {
+    let captures = FANSUB_EPISODE.captures(file_name)?;
+    let season = captures
+        .name("season")
+        .or_else(|| captures.name("season_long"))
+        .and_then(|season| season.as_str().parse().ok());
+    let title = captures["title"].replace('_', " ").trim().to_string();
+    if title.is_empty() {
+        return None;
+    }
+
+    Some(AnimeEpisode {
+        group: captures["group"].trim().to_string(),
+        title,
+        season,
+        episode: captures["episode"].parse().ok()?,
+        version: captures
+            .name("version")
+            .and_then(|version| version.as_str().parse().ok()),
+    })
+}
+
+pub fn parse_anime_from_torrents(
+    torrents: Vec<DebridTorrent>,
+    overrides: &Overrides,
+) -> anyhow::Result<Vec<Show>> {
+    let mut episodes = vec![];
+
+    for torrent in &torrents {
+        for file in &torrent.files {
+            let file_override = overrides.find(torrent, file);
+            // Files forced to a specific episode are handled by the shows library
+            if file_override
+                .is_some_and(|file_override| file_override.ignore || file_override.is_episode())
+            {
+                continue;
+            }
+            let Some(extension) = video_extension(&file.short_name) else {
+                continue;
+            };
+            let Some(mut anime_episode) = parse_anime_episode(&file.short_name) else {
+                continue;
+            };
+            if let Some(file_override) = file_override {
+                if let Some(title) = &file_override.title {
+                    anime_episode.title = title.clone();
+                }
+                anime_episode.season = file_override.season.or(anime_episode.season);
+                anime_episode.episode = file_override.episode.unwrap_or(anime_episode.episode);
+            }
+            episodes.push((anime_episode, extension, torrent, file));
+        }
+    }
+
+    // Fansub names are not understood by Plex, rename them to its episode scheme. Releases of
+    // the same episode keep their group, then their version, so their names don't collide.
+    let names = disambiguate(
+        episodes
+            .iter()
+            .map(|(anime_episode, extension, _, _)| {
+                [
+                    format!(
+                        "{} - s{:02}e{:02}",
+                        anime_episode.title,
+                        anime_episode.season_number(),
+                        anime_episode.episode
+                    ),
+                    format!(" - [{}]", anime_episode.group),
+                    format!(" v{}", anime_episode.version.unwrap_or(1)),
+                    format!(".{}", extension),
+                ]
+            })
+            .collect(),
+    );
+
+    let episodes =
+        episodes
+            .into_iter()
+            .zip(names)
+            .map(|((anime_episode, _, torrent, file), file_name)| {
+                (
+                    anime_episode.title.clone(),
+                    anime_episode.season_number(),
+                    ShowEpisode {
+                        numbers: anime_episode.episode..=anime_episode.episode,
+                        air_date: None,
+                        size: file.size,
+                        quality: Quality::parse(&file.name),
+                        file_name,
+                        debrid_file_metadata: DebridFileMetadata {
+                            account: torrent.account.clone(),
+                            torrent_id: torrent.id.clone(),
+                            file_id: file.id,
+                        },
+                    },
+                )
+            });
+
+    Ok(group_episodes(episodes))
+}
+
+/// Builds each name from its base, `[base, group, version, extension]`, adding the group and
+/// then the version only to the names that would collide without them.
+fn disambiguate(parts: Vec<[String; 4]>) -> Vec<String> {
+    let mut names = parts
+        .iter()
+        .map(|[base, _, _, extension]| format!("{}{}", base, extension))
+        .collect::<Vec<_>>();
+    for level in 1..=2 {
+        let mut counts = HashMap::new();
+        for name in &names {
+            *counts.entry(name.to_lowercase()).or_insert(0) += 1;
+        }
+        for (name, parts) in names.iter_mut().zip(&parts) {
+            if counts[&name.to_lowercase()] > 1 {
+                let (stem, extension) = name.split_at(name.len() - parts[3].len());
+                *name = format!("{}{}{}", stem, parts[level], extension);
+            }
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debrid::DebridFile;
+
+    fn torrent(id: &str, files: &[&str]) -> DebridTorrent {
+        DebridTorrent {
+            id: id.to_string(),
+            files: files
+                .iter()
+                .enumerate()
+                .map(|(index, name)| DebridFile {
+                    id: index as i64,
+                    name: name.to_string(),
+                    short_name: name.to_string(),
+                    size: 1_000,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn file_names(torrents: Vec<DebridTorrent>) -> Vec<String> {
+        let shows = parse_anime_from_torrents(torrents, &Overrides::default()).unwrap();
+        let mut file_names = shows
+            .iter()
+            .flat_map(|show| show.seasons.values())
+            .flat_map(|season| &season.episodes)
+            .map(|episode| episode.file_name.clone())
+            .collect::<Vec<_>>();
+        file_names.sort();
+        file_names
+    }
+
+    #[test]
+    fn it_only_keeps_video_files() {
+        assert_eq!(
+            file_names(vec![torrent(
+                "1",
+                &[
+                    "[SubsPlease] One Piece - 1071 (1080p).mkv",
+                    "[SubsPlease] One Piece - 1071 (1080p).ass",
+                ]
+            )]),
+            vec!["One Piece - s01e1071.mkv"]
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_group_and_version_of_colliding_episodes() {
+        assert_eq!(
+            file_names(vec![
+                torrent("1", &["[SubsPlease] Frieren - 05 (1080p).mkv"]),
+                torrent("2", &["[Erai-raws] Frieren - 05 [1080p].mkv"]),
+                torrent("3", &["[Erai-raws] Frieren - 05v2 [1080p].mkv"]),
+                torrent("4", &["[Erai-raws] Frieren - 06 [1080p].mkv"]),
+            ]),
+            vec![
+                "Frieren - s01e05 - [Erai-raws] v1.mkv",
+                "Frieren - s01e05 - [Erai-raws] v2.mkv",
+                "Frieren - s01e05 - [SubsPlease].mkv",
+                "Frieren - s01e06.mkv",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_parses_absolute_episodes() {
+        assert_eq!(
+            parse_anime_episode("[SubsPlease] One Piece - 1071 (1080p) [ABCD1234].mkv"),
+            Some(AnimeEpisode {
+                group: "SubsPlease".to_string(),
+                title: "One Piece".to_string(),
+                season: None,
+                episode: 1071,
+                version: None,
+            })
+        );
+    }
+
+    #[test]
+    fn it_parses_seasons() {
+        let short = parse_anime_episode("[Erai-raws] Spy x Family S2 - 05v2 [1080p].mkv").unwrap();
+        assert_eq!(short.title, "Spy x Family");
+        assert_eq!((short.season, short.episode), (Some(2), 5));
+        assert_eq!(short.version, Some(2));
+
+        let long = parse_anime_episode("[Group] Spy x Family - S02E05 [1080p].mkv").unwrap();
+        assert_eq!(long.title, "Spy x Family");
+        assert_eq!((long.season, long.episode), (Some(2), 5));
+    }
+
+    #[test]
+    fn it_keeps_dashes_inside_titles() {
+        let episode = parse_anime_episode(
+            "[SubsPlease] Re Zero - Starting Life in Another World - 12 (720p).mkv",
+        )
+        .unwrap();
+        assert_eq!(episode.title, "Re Zero - Starting Life in Another World");
+        assert_eq!(episode.episode, 12);
+    }
+
+    #[test]
+    fn it_ignores_scene_releases() {
+        assert_eq!(parse_anime_episode("Show.S01E01.1080p.WEB-DL.mkv"), None);
+        assert_eq!(parse_anime_episode("[Group] Some Movie (1080p).mkv"), None);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::content_database::ContentDatabase;
+use crate::debrid::{DebridAccounts, DebridFileMetadata, DebridTorrent};
+use crate::library::{MediaItem, parse_media_items};
+use crate::movies::video_extension;
+use crate::overrides::Overrides;
+use std::collections::{HashMap, HashSet};
+use tracing::info;
+
+/// Outcome of a backfill run.
+#[derive(Debug, Default, PartialEq)]
+pub struct BackfillReport {
+    pub torrents: usize,
+    pub changed_mappings: usize,
+    pub mapped_files: usize,
+    /// Video files nothing could be found for, as `(account, torrent name, file name)`.
+    pub unclassified: Vec<(String, String, String)>,
+}
+
+/// Seeds the content database with every file currently on the debrid accounts.
+pub async fn backfill(
+    debrid_accounts: &DebridAccounts,
+    content_database: &ContentDatabase,
+    overrides: &Overrides,
+) -> anyhow::Result<BackfillReport> {
+    info!("Listing torrents of every account...");
+    let torrents = debrid_accounts.list_torrents().await?;
+    info!(torrents = torrents.len(), message = "Parsing files");
+
+    let parsed_media_items = parse_media_items(&torrents, overrides)?;
+    let changed_mappings = content_database.record(&parsed_media_items)?;
+    content_database.prune(&torrents)?;
+    let media_items = content_database.media_items()?;
+    let ignored = parsed_media_items.ignored.into_iter().collect();
+
+    Ok(build_report(
+        &torrents,
+        &media_items,
+        &ignored,
+        changed_mappings,
+    ))
+}
+
+/// Reports how many of the files of the torrents are mapped, and the video files that are not.
+pub fn build_report(
+    torrents: &[DebridTorrent],
+    media_items: &HashMap<DebridFileMetadata, MediaItem>,
+    ignored: &HashSet<DebridFileMetadata>,
+    changed_mappings: usize,
+) -> BackfillReport {
+    let mut report = BackfillReport {
+        torrents: torrents.len(),
+        changed_mappings,
+        ..Default::default()
+    };
+
+    for torrent in torrents {
+        for file in &torrent.files {
+            let debrid_file_metadata = DebridFileMetadata {
+                account: torrent.account.clone(),
+                torrent_id: torrent.id.clone(),
+                file_id: file.id,
+            };
+            if media_items.contains_key(&debrid_file_metadata) {
+                report.mapped_files += 1;
+            } else if !ignored.contains(&debrid_file_metadata)
+                && video_extension(&file.name).is_some()
+            {
+                report.unclassified.push((
+                    torrent.account.clone(),
+                    torrent.name.clone().unwrap_or_else(|| torrent.id.clone()),
+                    file.name.clone(),
+                ));
+            }
+        }
+    }
+    report.unclassified.sort();
+    report
+}
+
+impl BackfillReport {
+    pub fn print(&self) {
+        println!(
+            "Scanned {} torrents: {} mapped files ({} changed), {} unclassified video files",
+            self.torrents,
+            self.mapped_files,
+            self.changed_mappings,
+            self.unclassified.len()
+        );
+        for (account, torrent_name, file_name) in &self.unclassified {
+            println!("[{}] {}: {}", account, torrent_name, file_name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debrid::DebridFile;
+
+    #[test]
+    fn it_reports_unclassified_videos() {
+        let torrents = vec![DebridTorrent {
+            account: "torbox-1".to_string(),
+            id: "1".to_string(),
+            name: Some("Show.S01.1080p".to_string()),
+            files: vec![
+                DebridFile {
+                    id: 1,
+                    name: "Show.S01.1080p/Show.S01E01.mkv".to_string(),
+                    short_name: "Show.S01E01.mkv".to_string(),
+                    size: 10,
+                },
+                DebridFile {
+                    id: 2,
+                    name: "Show.S01.1080p/Show.S01E02.mkv".to_string(),
+                    short_name: "Show.S01E02.mkv".to_string(),
+                    size: 10,
+                },
+                DebridFile {
+                    id: 3,
+                    name: "Show.S01.1080p/home video.mkv".to_string(),
+                    short_name: "home video.mkv".to_string(),
+                    size: 10,
+                },
+                DebridFile {
+                    id: 4,
+                    name: "Show.S01.1080p/Release.nfo".to_string(),
+                    short_name: "Release.nfo".to_string(),
+                    size: 1,
+                },
+            ],
+            ..Default::default()
+        }];
+        let media_items = parse_media_items(&torrents, &Overrides::default())
+            .unwrap()
+            .parsed
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        let report = build_report(&torrents, &media_items, &HashSet::new(), 1);
+
+        assert_eq!(report.torrents, 1);
+        assert_eq!(report.changed_mappings, 1);
+        assert_eq!(report.mapped_files, 2);
+        assert_eq!(
+            report.unclassified,
+            vec![(
+                "torbox-1".to_string(),
+                "Show.S01.1080p".to_string(),
+                "Show.S01.1080p/home video.mkv".to_string()
+            )]
+        );
+    }
+}
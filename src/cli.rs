@@ -1,14 +1,31 @@
-use clap::Parser;
+use crate::debrid::Provider;
+use anyhow::Context;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::collections::HashSet;
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
-    /// Your debrid service API key. Currently only supports TorBox.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Your debrid service API key.
     #[clap(long, env = "API_KEY")]
-    pub api_key: String,
+    pub api_key: Option<String>,
+
+    /// The debrid service the API key belongs to.
+    #[clap(long, env = "PROVIDER", value_enum, default_value_t = Provider::Torbox)]
+    pub provider: Provider,
+
+    /// Additional debrid accounts, formatted as `<provider>:<api key>[:<name>]`. Files of all
+    /// the accounts are merged into the same library.
+    #[clap(long = "account", env = "ACCOUNTS", value_delimiter = ',')]
+    pub accounts: Vec<AccountConfig>,
 
     /// The address the WebDav server will listen to.
     #[clap(long, env = "ADDRESS", default_value_t = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 3000)
@@ -18,4 +35,182 @@ pub struct Cli {
     /// Debrid service file refresh interval in seconds.
     #[clap(short, long, default_value_t = 60 * 10, env = "REFRESH_INTERVAL")]
     pub refresh_interval: u64,
+
+    /// Path of the database mapping debrid files to the media they contain.
+    #[clap(long, default_value = "javelot.db", env = "DATABASE_PATH")]
+    pub database_path: PathBuf,
+
+    /// Path of the snapshot of the filesystem, saved after each refresh and served at startup
+    /// until the first refresh completes.
+    #[clap(
+        long,
+        default_value = "filesystem_snapshot.json",
+        env = "SNAPSHOT_PATH"
+    )]
+    pub snapshot_path: PathBuf,
+
+    /// Path of a TOML file fixing what the parsers get wrong. It is reloaded when modified.
+    #[clap(long, env = "OVERRIDES_PATH")]
+    pub overrides_path: Option<PathBuf>,
+
+    /// Path of a TOML config file, see the README for the available settings.
+    #[clap(long, env = "CONFIG_PATH")]
+    pub config_path: Option<PathBuf>,
+
+    /// TMDB API key. When set, show folders are named after the canonical title, year and id of
+    /// the show, e.g. `Show Name (2019) {tvdb-12345}`.
+    #[clap(long, env = "METADATA_API_KEY")]
+    pub metadata_api_key: Option<String>,
+
+    /// Base URL of the TMDB compatible API used to look shows up.
+    #[clap(
+        long,
+        default_value = "https://api.themoviedb.org",
+        env = "METADATA_API_URL"
+    )]
+    pub metadata_api_url: String,
+
+    /// Path of the file caching show lookups.
+    #[clap(
+        long,
+        default_value = "metadata_cache.json",
+        env = "METADATA_CACHE_PATH"
+    )]
+    pub metadata_cache_path: PathBuf,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Maps every file currently on the debrid accounts in the content database, then prints
+    /// the video files that could not be classified.
+    Backfill,
+}
+
+impl Cli {
+    /// Every configured account, `--api-key` first, each with a unique name.
+    pub fn account_configs(&self) -> anyhow::Result<Vec<AccountConfig>> {
+        let mut configs = vec![];
+        if let Some(api_key) = &self.api_key {
+            configs.push(AccountConfig {
+                provider: self.provider,
+                api_key: api_key.clone(),
+                name: None,
+            });
+        }
+        configs.extend(self.accounts.iter().cloned());
+        if configs.is_empty() {
+            anyhow::bail!("No debrid account configured, use --api-key or --account");
+        }
+
+        let mut names = HashSet::new();
+        for config in &mut configs {
+            let name = config
+                .name
+                .get_or_insert_with(|| default_account_name(config.provider, &config.api_key));
+            if !names.insert(name.clone()) {
+                anyhow::bail!("Account name {} is used more than once", name);
+            }
+        }
+        Ok(configs)
+    }
+}
+
+/// Names an account after its provider and a hash of its API key, e.g. `torbox-3f2a9c1e`.
+/// The name keys the content database, so it must not change when accounts are reordered.
+fn default_account_name(provider: Provider, api_key: &str) -> String {
+    // FNV-1a, unlike the std hashers it is guaranteed to be the same across releases
+    let hash = api_key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{}-{:08x}", provider, hash >> 32)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountConfig {
+    pub provider: Provider,
+    pub api_key: String,
+    pub name: Option<String>,
+}
+
+impl FromStr for AccountConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let provider = parts.next().unwrap_or_default();
+        let provider = Provider::from_str(provider, true)
+            .map_err(|e| anyhow::anyhow!(e))
+            .context("Unknown debrid provider")?;
+        let api_key = parts
+            .next()
+            .filter(|api_key| !api_key.is_empty())
+            .context("Missing API key, expected <provider>:<api key>[:<name>]")?;
+        let name = parts
+            .next()
+            .filter(|name| !name.is_empty())
+            .map(String::from);
+        Ok(AccountConfig {
+            provider,
+            api_key: api_key.to_string(),
+            name,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_accounts() {
+        assert_eq!(
+            "real-debrid:KEY:alice".parse::<AccountConfig>().unwrap(),
+            AccountConfig {
+                provider: Provider::RealDebrid,
+                api_key: "KEY".to_string(),
+                name: Some("alice".to_string()),
+            }
+        );
+        assert_eq!("torbox:KEY".parse::<AccountConfig>().unwrap().name, None);
+        assert!("torbox".parse::<AccountConfig>().is_err());
+        assert!("premiumize:KEY".parse::<AccountConfig>().is_err());
+    }
+
+    #[test]
+    fn it_names_accounts() {
+        let cli = Cli::parse_from([
+            "javelot",
+            "--api-key",
+            "KEY",
+            "--account",
+            "torbox:OTHER",
+            "--account",
+            "real-debrid:THIRD:bob",
+        ]);
+        let names = cli
+            .account_configs()
+            .unwrap()
+            .into_iter()
+            .map(|config| config.name.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["torbox-50d84a19", "torbox-b4f2d3bc", "bob"]);
+    }
+
+    #[test]
+    fn it_keeps_default_names_stable() {
+        assert_eq!(
+            default_account_name(Provider::Torbox, "KEY"),
+            "torbox-50d84a19"
+        );
+        assert_eq!(
+            default_account_name(Provider::RealDebrid, "KEY"),
+            "real-debrid-50d84a19"
+        );
+        // The same key given twice would be the same account
+        assert!(
+            Cli::parse_from(["javelot", "--api-key", "KEY", "--account", "torbox:KEY"])
+                .account_configs()
+                .is_err()
+        );
+    }
 }
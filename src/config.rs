@@ -0,0 +1,131 @@
+use crate::dav_server::ImportConfig;
+use crate::feeds::FeedsConfig;
+use crate::media_server::MediaServerConfig;
+use crate::naming::Naming;
+use crate::plex::PlexConfig;
+use crate::qbittorrent::DownloadClientConfig;
+use crate::quality::QualityPolicy;
+use crate::webhooks::WebhookConfig;
+use anyhow::Context;
+use serde::Deserialize;
+use std::path::Path;
+
+/// Settings read from the TOML file passed with `--config-path`, every section is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub quality: QualityPolicy,
+    pub naming: Naming,
+    pub media_servers: Vec<MediaServerConfig>,
+    /// Added to the media servers, see [`PlexConfig`].
+    pub plex: Option<PlexConfig>,
+    pub webhooks: Vec<WebhookConfig>,
+    /// Serves the qBittorrent API to Sonarr and Radarr when set.
+    pub download_client: Option<DownloadClientConfig>,
+    /// Serves RSS feeds of releases when set.
+    pub feeds: Option<FeedsConfig>,
+    /// Serves the import folder when set.
+    pub import: Option<ImportConfig>,
+}
+
+impl Config {
+    /// Loads the config file, the defaults apply without one.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Config::default());
+        };
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        Config::parse(&content)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let mut config: Config = toml::from_str(content)?;
+        if let Some(plex) = config.plex.take() {
+            config.media_servers.insert(0, plex.into());
+        }
+        config
+            .quality
+            .validate()
+            .context("Invalid [quality] section")?;
+        for media_server in &config.media_servers {
+            media_server
+                .validate(&config.naming)
+                .with_context(|| format!("Invalid media server {}", media_server.url))?;
+        }
+        for webhook in &config.webhooks {
+            webhook
+                .validate()
+                .with_context(|| format!("Invalid webhook {}", webhook.url))?;
+        }
+        if let Some(download_client) = &config.download_client {
+            download_client
+                .validate()
+                .context("Invalid [download_client] section")?;
+        }
+        if let Some(feeds) = &config.feeds {
+            feeds.validate().context("Invalid [feeds] section")?;
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media_server::MediaServerKind;
+
+    #[test]
+    fn it_parses_the_quality_policy() {
+        let config = Config::parse(
+            r#"
+            [quality]
+            resolutions = ["1080p", "720p"]
+            sources = ["WEB-DL", "BluRay"]
+            blocked_groups = ["YIFY"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.quality.resolutions, vec!["1080p", "720p"]);
+        assert!(config.quality.prefer_hdr);
+
+        assert!(Config::parse("[quality]\nsources = [\"VHS\"]").is_err());
+        assert!(Config::parse("[quality]\nresolutions = [\"4K\"]").is_err());
+        assert!(Config::parse("[quality]\nresolutions = [\"1080i\"]").is_err());
+        assert!(Config::parse("[quality]\nresolutions = [\"1080p-ish\"]").is_err());
+        assert!(Config::parse("[quality]\nresolution = [\"1080p\"]").is_err());
+    }
+
+    #[test]
+    fn it_checks_the_media_servers() {
+        let media_server = |kind: &str, sections: &str| {
+            Config::parse(&format!(
+                "[[media_servers]]\nkind = \"{}\"\nurl = \"http://127.0.0.1:32400\"\ntoken = \"token\"\nmount_path = \"/mnt\"\n{}",
+                kind, sections
+            ))
+        };
+        let config = media_server("plex", "sections = { shows = 1 }").unwrap();
+        assert_eq!(config.media_servers[0].debounce_seconds, 30);
+        assert!(media_server("plex", "sections = { series = 1 }").is_err());
+        assert!(media_server("plex", "").is_err());
+        assert!(media_server("jellyfin", "").is_ok());
+        assert!(media_server("emby", "sections = { shows = 1 }").is_err());
+    }
+
+    #[test]
+    fn it_reads_the_plex_section_as_a_media_server() {
+        let plex = |sections: &str| {
+            Config::parse(&format!(
+                "[plex]\nurl = \"http://127.0.0.1:32400\"\ntoken = \"token\"\nmount_path = \"/mnt\"\nsections = {}",
+                sections
+            ))
+        };
+        let config = plex("{ shows = 1 }").unwrap();
+        assert_eq!(config.media_servers.len(), 1);
+        assert_eq!(config.media_servers[0].kind, MediaServerKind::Plex);
+        assert_eq!(config.media_servers[0].debounce_seconds, 30);
+        assert!(plex("{ series = 1 }").is_err());
+        assert!(plex("{}").is_err());
+    }
+}
@@ -0,0 +1,490 @@
+use crate::debrid::{DebridFileMetadata, DebridTorrent};
+use crate::library::{MediaItem, ParsedMediaItems};
+use anyhow::Context;
+use rusqlite::{Connection, params};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+use time::OffsetDateTime;
+use tracing::warn;
+
+/// Mapping created by the parsers.
+const SOURCE_PARSED: &str = "parsed";
+/// Mapping created by the parsers with the help of an override.
+const SOURCE_OVERRIDE: &str = "override";
+
+/// Schema migrations, the `user_version` of the database is the number of applied ones.
+const MIGRATIONS: [&str; 4] = [
+    "CREATE TABLE IF NOT EXISTS mappings (
+        account TEXT NOT NULL,
+        torrent_id TEXT NOT NULL,
+        file_id INTEGER NOT NULL,
+        media TEXT NOT NULL,
+        source TEXT NOT NULL CHECK (source IN ('parsed', 'manual')),
+        first_seen INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL,
+        PRIMARY KEY (account, torrent_id, file_id)
+    );",
+    "ALTER TABLE mappings RENAME TO mappings_old;
+    CREATE TABLE mappings (
+        account TEXT NOT NULL,
+        torrent_id TEXT NOT NULL,
+        file_id INTEGER NOT NULL,
+        media TEXT NOT NULL,
+        source TEXT NOT NULL CHECK (source IN ('parsed', 'override', 'manual')),
+        first_seen INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL,
+        PRIMARY KEY (account, torrent_id, file_id)
+    );
+    INSERT INTO mappings SELECT * FROM mappings_old;
+    DROP TABLE mappings_old;",
+    // Episodes record the range of episodes of their file instead of a single number
+    "UPDATE mappings
+    SET media = json_remove(
+        json_set(
+            media,
+            '$.episode.numbers',
+            json_object(
+                'start', json_extract(media, '$.episode.number'),
+                'end', json_extract(media, '$.episode.number')
+            )
+        ),
+        '$.episode.number'
+    )
+    WHERE json_extract(media, '$.kind') = 'episode'
+    AND json_extract(media, '$.episode.number') IS NOT NULL;",
+    // Torrents added through the download client API, and its categories
+    "CREATE TABLE downloads (
+        hash TEXT PRIMARY KEY,
+        category TEXT NOT NULL,
+        added_at INTEGER NOT NULL
+    );
+    CREATE TABLE categories (
+        name TEXT PRIMARY KEY
+    );",
+];
+
+/// On-disk source of truth mapping debrid files to the media they contain.
+///
+/// A file keeps the mapping it was first given, so paths stay the same across restarts and
+/// parser changes. Mappings coming from the overrides file follow its changes. Mappings can be
+/// fixed by hand by editing the `media` of a row and setting its `source` to `manual`.
+pub struct ContentDatabase {
+    connection: Mutex<Connection>,
+}
+
+impl ContentDatabase {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut connection = Connection::open(path)
+            .with_context(|| format!("Failed to open database {}", path.display()))?;
+        migrate(&mut connection).context("Failed to migrate database schema")?;
+        Ok(ContentDatabase {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Records what the parsers found:
+    /// - files seen for the first time get their parsed mapping, known files keep theirs unless
+    ///   it came from an override that no longer applies,
+    /// - overridden files always get the overridden mapping, unless fixed by hand,
+    /// - ignored files lose their mapping, so do the files of the parsed torrents whose override
+    ///   was removed and that the parsers can't read on their own.
+    ///
+    /// Returns the number of mappings that changed.
+    pub fn record(&self, media_items: &ParsedMediaItems) -> anyhow::Result<usize> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let mut changed = 0;
+        {
+            let mut insert_parsed = transaction.prepare_cached(
+                "INSERT INTO mappings (account, torrent_id, file_id, media, source, first_seen, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)
+                 ON CONFLICT (account, torrent_id, file_id) DO UPDATE
+                 SET media = excluded.media, source = excluded.source, updated_at = excluded.updated_at
+                 WHERE mappings.source = 'override'",
+            )?;
+            for (debrid_file_metadata, media_item) in &media_items.parsed {
+                changed += insert_parsed.execute(params![
+                    debrid_file_metadata.account,
+                    debrid_file_metadata.torrent_id,
+                    debrid_file_metadata.file_id,
+                    serde_json::to_string(media_item)?,
+                    SOURCE_PARSED,
+                    now,
+                ])?;
+            }
+
+            let mut upsert_overridden = transaction.prepare_cached(
+                "INSERT INTO mappings (account, torrent_id, file_id, media, source, first_seen, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)
+                 ON CONFLICT (account, torrent_id, file_id) DO UPDATE
+                 SET media = excluded.media, source = excluded.source, updated_at = excluded.updated_at
+                 WHERE mappings.source != 'manual'
+                 AND (mappings.source != excluded.source OR mappings.media != excluded.media)",
+            )?;
+            for (debrid_file_metadata, media_item) in &media_items.overridden {
+                changed += upsert_overridden.execute(params![
+                    debrid_file_metadata.account,
+                    debrid_file_metadata.torrent_id,
+                    debrid_file_metadata.file_id,
+                    serde_json::to_string(media_item)?,
+                    SOURCE_OVERRIDE,
+                    now,
+                ])?;
+            }
+
+            let mut delete_ignored = transaction.prepare_cached(
+                "DELETE FROM mappings WHERE account = ?1 AND torrent_id = ?2 AND file_id = ?3",
+            )?;
+            for debrid_file_metadata in &media_items.ignored {
+                changed += delete_ignored.execute(params![
+                    debrid_file_metadata.account,
+                    debrid_file_metadata.torrent_id,
+                    debrid_file_metadata.file_id,
+                ])?;
+            }
+
+            // Files found by the parsers on their own already lost their override mapping above
+            let overridden = media_items
+                .overridden
+                .iter()
+                .map(|(debrid_file_metadata, _)| debrid_file_metadata)
+                .collect::<HashSet<_>>();
+            let mut select_overridden = transaction.prepare_cached(
+                "SELECT file_id FROM mappings
+                 WHERE account = ?1 AND torrent_id = ?2 AND source = 'override'",
+            )?;
+            for (account, torrent_id) in &media_items.torrents {
+                let file_ids = select_overridden
+                    .query_map(params![account, torrent_id], |row| row.get(0))?
+                    .collect::<Result<Vec<i64>, _>>()?;
+                for file_id in file_ids {
+                    let debrid_file_metadata = DebridFileMetadata {
+                        account: account.clone(),
+                        torrent_id: torrent_id.clone(),
+                        file_id,
+                    };
+                    if !overridden.contains(&debrid_file_metadata) {
+                        changed += delete_ignored.execute(params![account, torrent_id, file_id])?;
+                    }
+                }
+            }
+        }
+        transaction.commit()?;
+        Ok(changed)
+    }
+
+    /// Deletes the mappings of the torrents that are no longer listed, returns how many.
+    pub fn prune(&self, torrents: &[DebridTorrent]) -> anyhow::Result<usize> {
+        let listed = torrents
+            .iter()
+            .map(|torrent| (torrent.account.as_str(), torrent.id.as_str()))
+            .collect::<HashSet<_>>();
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let mut removed = 0;
+        {
+            let mut select_torrents =
+                transaction.prepare_cached("SELECT DISTINCT account, torrent_id FROM mappings")?;
+            let mapped_torrents = select_torrents
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<Vec<(String, String)>, _>>()?;
+            let mut delete_torrent = transaction
+                .prepare_cached("DELETE FROM mappings WHERE account = ?1 AND torrent_id = ?2")?;
+            for (account, torrent_id) in mapped_torrents {
+                if !listed.contains(&(account.as_str(), torrent_id.as_str())) {
+                    removed += delete_torrent.execute(params![account, torrent_id])?;
+                }
+            }
+        }
+        transaction.commit()?;
+        Ok(removed)
+    }
+
+    /// Loads the media item of every mapped file.
+    pub fn media_items(&self) -> anyhow::Result<HashMap<DebridFileMetadata, MediaItem>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare_cached("SELECT account, torrent_id, file_id, media FROM mappings")?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                DebridFileMetadata {
+                    account: row.get(0)?,
+                    torrent_id: row.get(1)?,
+                    file_id: row.get(2)?,
+                },
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+
+        let mut media_items = HashMap::new();
+        for row in rows {
+            let (debrid_file_metadata, media) = row?;
+            match serde_json::from_str(&media) {
+                Ok(media_item) => {
+                    media_items.insert(debrid_file_metadata, media_item);
+                }
+                Err(e) => warn!(
+                    "Ignoring invalid mapping of file {} of torrent {}: {}",
+                    debrid_file_metadata.file_id, debrid_file_metadata.torrent_id, e
+                ),
+            }
+        }
+        Ok(media_items)
+    }
+
+    /// Records a torrent added through the download client API, by info hash.
+    pub fn add_download(&self, hash: &str, category: &str) -> anyhow::Result<()> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT INTO downloads (hash, category, added_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT (hash) DO UPDATE SET category = excluded.category",
+            params![hash.to_lowercase(), category, now],
+        )?;
+        Ok(())
+    }
+
+    /// Forgets a torrent added through the download client API, the torrent itself is kept.
+    pub fn remove_download(&self, hash: &str) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "DELETE FROM downloads WHERE hash = ?1",
+            params![hash.to_lowercase()],
+        )?;
+        Ok(())
+    }
+
+    /// Loads every torrent added through the download client API, by info hash.
+    pub fn downloads(&self) -> anyhow::Result<HashMap<String, Download>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement =
+            connection.prepare_cached("SELECT hash, category, added_at FROM downloads")?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                Download {
+                    category: row.get(1)?,
+                    added_at: row.get(2)?,
+                },
+            ))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    pub fn add_category(&self, name: &str) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT INTO categories (name) VALUES (?1) ON CONFLICT DO NOTHING",
+            params![name],
+        )?;
+        Ok(())
+    }
+
+    /// Lists the categories created or given to a download, sorted.
+    pub fn categories(&self) -> anyhow::Result<Vec<String>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare_cached(
+            "SELECT name FROM categories
+             UNION SELECT category FROM downloads WHERE category != ''
+             ORDER BY 1",
+        )?;
+        let rows = statement.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+/// A torrent added through the download client API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Download {
+    pub category: String,
+    /// Unix timestamp.
+    pub added_at: i64,
+}
+
+fn migrate(connection: &mut Connection) -> anyhow::Result<()> {
+    let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", index + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movies::Movie;
+
+    fn movie(title: &str) -> MediaItem {
+        MediaItem::Movie(Movie {
+            title: title.to_string(),
+            year: Some(1995),
+            debrid_file_metadata: DebridFileMetadata::default(),
+            size: 0,
+            quality: Default::default(),
+            extension: "mkv".to_string(),
+        })
+    }
+
+    fn file(file_id: i64) -> DebridFileMetadata {
+        DebridFileMetadata {
+            account: "torbox-1".to_string(),
+            torrent_id: "1".to_string(),
+            file_id,
+        }
+    }
+
+    fn parsed(media_items: Vec<(DebridFileMetadata, MediaItem)>) -> ParsedMediaItems {
+        ParsedMediaItems {
+            parsed: media_items,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_keeps_the_first_mapping() {
+        let database = ContentDatabase::open(Path::new(":memory:")).unwrap();
+
+        let changed = database
+            .record(&parsed(vec![
+                (file(1), movie("Heat")),
+                (file(2), movie("Ronin")),
+            ]))
+            .unwrap();
+        assert_eq!(changed, 2);
+
+        let changed = database
+            .record(&parsed(vec![
+                (file(1), movie("Heat 2")),
+                (file(3), movie("Thief")),
+            ]))
+            .unwrap();
+        assert_eq!(changed, 1);
+
+        let media_items = database.media_items().unwrap();
+        assert_eq!(media_items.len(), 3);
+        assert_eq!(media_items[&file(1)], movie("Heat"));
+    }
+
+    #[test]
+    fn it_follows_overrides() {
+        let database = ContentDatabase::open(Path::new(":memory:")).unwrap();
+        database
+            .record(&parsed(vec![
+                (file(1), movie("Heat")),
+                (file(2), movie("Ronin")),
+            ]))
+            .unwrap();
+
+        let changed = database
+            .record(&ParsedMediaItems {
+                overridden: vec![(file(1), movie("Heat (Remastered)"))],
+                ignored: vec![file(2)],
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(changed, 2);
+        let media_items = database.media_items().unwrap();
+        assert_eq!(media_items[&file(1)], movie("Heat (Remastered)"));
+        assert!(!media_items.contains_key(&file(2)));
+
+        // Once the override is gone the parsed mapping comes back
+        database
+            .record(&parsed(vec![(file(1), movie("Heat"))]))
+            .unwrap();
+        assert_eq!(database.media_items().unwrap()[&file(1)], movie("Heat"));
+    }
+
+    #[test]
+    fn it_forgets_removed_overrides_of_unreadable_files() {
+        let database = ContentDatabase::open(Path::new(":memory:")).unwrap();
+        let torrents = vec![("torbox-1".to_string(), "1".to_string())];
+        database
+            .record(&ParsedMediaItems {
+                overridden: vec![(file(1), movie("Pilot"))],
+                torrents: torrents.clone(),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(database.media_items().unwrap()[&file(1)], movie("Pilot"));
+
+        // The override is gone and the parsers can't read `pilot.mkv` on their own
+        let changed = database
+            .record(&ParsedMediaItems {
+                torrents,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(changed, 1);
+        assert!(database.media_items().unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_prunes_removed_torrents() {
+        let database = ContentDatabase::open(Path::new(":memory:")).unwrap();
+        let other_torrent = DebridFileMetadata {
+            torrent_id: "2".to_string(),
+            ..file(1)
+        };
+        database
+            .record(&parsed(vec![
+                (file(1), movie("Heat")),
+                (file(2), movie("Heat")),
+                (other_torrent.clone(), movie("Ronin")),
+            ]))
+            .unwrap();
+
+        let listed = DebridTorrent {
+            account: "torbox-1".to_string(),
+            id: "2".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(database.prune(&[listed]).unwrap(), 2);
+        let media_items = database.media_items().unwrap();
+        assert_eq!(media_items.keys().collect::<Vec<_>>(), vec![&other_torrent]);
+    }
+
+    #[test]
+    fn it_migrates_episode_numbers() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(MIGRATIONS[0]).unwrap();
+        connection.pragma_update(None, "user_version", 1).unwrap();
+        connection
+            .execute(
+                r#"INSERT INTO mappings VALUES ('torbox-1', '1', 1,
+                '{"kind":"episode","library":"shows","show":"Show","season":1,"episode":{"number":3,"file_name":"Show.S01E03.mkv"}}',
+                'parsed', 0, 0)"#,
+                [],
+            )
+            .unwrap();
+        migrate(&mut connection).unwrap();
+        let database = ContentDatabase {
+            connection: Mutex::new(connection),
+        };
+
+        let MediaItem::Episode { episode, .. } = &database.media_items().unwrap()[&file(1)] else {
+            panic!("expected an episode");
+        };
+        assert_eq!(episode.numbers, 3..=3);
+    }
+
+    #[test]
+    fn it_skips_invalid_rows() {
+        let database = ContentDatabase::open(Path::new(":memory:")).unwrap();
+        database
+            .connection
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO mappings VALUES ('torbox-1', '1', 1, '{}', 'manual', 0, 0)",
+                [],
+            )
+            .unwrap();
+
+        assert!(database.media_items().unwrap().is_empty());
+    }
+}
@@ -5,6 +5,7 @@ use axum::extract::Request;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use std::path::PathBuf;
+use tracing::error;
 
 pub(super) async fn get_handler(req: Request, path: PathBuf, app_state: AppState) -> Response {
     let normalized_path = {
@@ -21,25 +22,43 @@ pub(super) async fn get_handler(req: Request, path: PathBuf, app_state: AppState
     };
 
     // Get the file from fake filesystem
-    let node = {
-        let fs = app_state.fake_file_system.lock().unwrap();
-        fs.read_node(&normalized_path).cloned()
-    };
+    let node = app_state
+        .fake_file_system
+        .load()
+        .read_node(&normalized_path)
+        .cloned();
 
     if let Some(node) = node {
         match node {
             Node::File(file) => {
                 let range_header = req.headers().get("Range").cloned();
 
-                let reqwest_response = app_state
-                    .torbox_client
+                let Some(debrid_provider) = app_state
+                    .debrid_accounts
+                    .get(&file.download_details.account)
+                else {
+                    error!(
+                        "account {} of {} is not configured",
+                        file.download_details.account,
+                        normalized_path.display()
+                    );
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                };
+
+                let reqwest_response = match debrid_provider
                     .torrent_stream(
-                        file.download_details.0,
-                        file.download_details.1,
+                        &file.download_details.torrent_id,
+                        file.download_details.file_id,
                         range_header,
                     )
                     .await
-                    .unwrap();
+                {
+                    Ok(reqwest_response) => reqwest_response,
+                    Err(e) => {
+                        error!("Failed to stream {}: {:?}", normalized_path.display(), e);
+                        return StatusCode::BAD_GATEWAY.into_response();
+                    }
+                };
 
                 let status = reqwest_response.status();
                 let headers = reqwest_response.headers().clone();
@@ -1,6 +1,7 @@
 use crate::AppState;
 use crate::dav_server::get_handler::get_handler;
 use crate::dav_server::propfind_handler::propfind_handler;
+use crate::dav_server::put_handler::put_handler;
 use axum::extract;
 use axum::extract::{Request, State};
 use axum::http::{HeaderValue, Method, StatusCode};
@@ -11,6 +12,13 @@ use webdav_meta::methods::PROPFIND;
 
 mod get_handler;
 mod propfind_handler;
+mod put_handler;
+
+pub use put_handler::ImportConfig;
+
+/// Folder `.torrent` and magnet files can be uploaded to, to add them to the debrid account.
+/// Only served with an `[import]` section.
+pub const IMPORT_FOLDER: &str = "/_import";
 
 pub async fn webdav_handler(
     method: Method,
@@ -23,13 +31,31 @@ pub async fn webdav_handler(
         None => path::Path::new("/").into(),
     };
 
+    // Served from the snapshot of a previous run until the first refresh completes
+    let is_stale = app_state.fake_file_system.load().is_stale();
+
     let mut resp = match method {
         Method::GET => get_handler(req, path, app_state).await,
+        Method::PUT => {
+            put_handler(
+                req,
+                path,
+                app_state.import_client.as_deref(),
+                &app_state.refresh_trigger,
+            )
+            .await
+        }
         _ if method == PROPFIND.as_ref() => propfind_handler(req, path, app_state).await,
         _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
     };
     resp.headers_mut()
         .append("dav", HeaderValue::from_static("1"));
+    if is_stale {
+        resp.headers_mut().append(
+            "warning",
+            HeaderValue::from_static("110 - \"Response is Stale\""),
+        );
+    }
 
     resp
 }
@@ -34,7 +34,7 @@ pub(super) async fn propfind_handler(req: Request, path: PathBuf, app_state: App
         PathBuf::from(normalized)
     };
 
-    let fs = app_state.fake_file_system.lock().unwrap();
+    let fs = app_state.fake_file_system.load();
 
     if let Some(node) = fs.read_node(&new_path) {
         match node {
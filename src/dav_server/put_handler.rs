@@ -0,0 +1,410 @@
+use crate::cli::AccountConfig;
+use crate::dav_server::IMPORT_FOLDER;
+use crate::debrid::{NewTorrent, Provider};
+use crate::torbox_client::Torbox;
+use axum::extract::Request;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use http_body_util::LengthLimitError;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use tokio::sync::Notify;
+use tracing::{error, info};
+
+/// Largest file accepted, the `.torrent` files of large releases weigh a few megabytes.
+const MAX_FILE_SIZE: usize = 10 * 1024 * 1024;
+
+/// The `[import]` section of the config file, serves the import folder when set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImportConfig {
+    /// Name of the TorBox account the imported torrents are added to.
+    pub account: String,
+}
+
+impl ImportConfig {
+    /// The client of the account torrents are imported to, which must be a TorBox one.
+    pub fn client(&self, accounts: &[AccountConfig]) -> anyhow::Result<Torbox> {
+        let Some(account) = accounts
+            .iter()
+            .find(|account| account.name.as_deref() == Some(self.account.as_str()))
+        else {
+            anyhow::bail!("Unknown account {} in [import]", self.account);
+        };
+        if account.provider != Provider::Torbox {
+            anyhow::bail!("Account {} in [import] is not a TorBox one", self.account);
+        }
+        Ok(Torbox::new(account.api_key.clone()))
+    }
+}
+
+/// Adds the torrent, or the magnet links, of a file uploaded to the import folder to the
+/// TorBox account of the `[import]` section. The file itself is not kept.
+///
+/// Every torrent of the file is tried, those that fail are listed in a `502` response.
+pub(super) async fn put_handler(
+    req: Request,
+    path: PathBuf,
+    import_client: Option<&Torbox>,
+    refresh_trigger: &Notify,
+) -> Response {
+    let path = Path::new("/").join(path);
+    let (Some(import_client), Some(file_name)) = (
+        import_client,
+        path.file_name()
+            .filter(|_| path.parent() == Some(Path::new(IMPORT_FOLDER))),
+    ) else {
+        // The rest of the filesystem is read-only
+        return StatusCode::FORBIDDEN.into_response();
+    };
+    let file_name = file_name.to_string_lossy().to_string();
+
+    let content = match axum::body::to_bytes(req.into_body(), MAX_FILE_SIZE).await {
+        Ok(content) => content,
+        Err(e) => {
+            let status = if e.into_inner().is::<LengthLimitError>() {
+                StatusCode::PAYLOAD_TOO_LARGE
+            } else {
+                // e.g. the upload was aborted
+                StatusCode::BAD_REQUEST
+            };
+            return status.into_response();
+        }
+    };
+    // Some clients create an empty file before uploading its content
+    if content.is_empty() {
+        return StatusCode::CREATED.into_response();
+    }
+    let torrents = match read_import(&file_name, &content) {
+        Ok(torrents) => torrents,
+        Err((status, message)) => return (status, message).into_response(),
+    };
+
+    let count = torrents.len();
+    let mut failures = vec![];
+    for torrent in torrents {
+        let name = match &torrent {
+            NewTorrent::Magnet(magnet) => magnet.clone(),
+            NewTorrent::File { name, .. } => name.clone(),
+        };
+        match import_client.create_torrent(torrent).await {
+            Ok(hash) => info!(hash, file_name, message = "Imported torrent"),
+            Err(e) => {
+                error!("Failed to import {} of {}: {:?}", name, file_name, e);
+                failures.push(format!("{}: {:#}", name, e));
+            }
+        }
+    }
+
+    // Cached torrents are ready right away
+    if failures.len() < count {
+        refresh_trigger.notify_one();
+    }
+    if !failures.is_empty() {
+        let message = format!(
+            "Imported {} of {} torrents, failed to import:\n{}\n",
+            count - failures.len(),
+            count,
+            failures.join("\n")
+        );
+        return (StatusCode::BAD_GATEWAY, message).into_response();
+    }
+    StatusCode::CREATED.into_response()
+}
+
+/// Reads a `.torrent` file, or the magnet links of a `.magnet` or `.txt` file, one per line.
+fn read_import(
+    file_name: &str,
+    content: &[u8],
+) -> Result<Vec<NewTorrent>, (StatusCode, &'static str)> {
+    let extension = Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_lowercase);
+    match extension.as_deref() {
+        Some("torrent") => Ok(vec![NewTorrent::File {
+            name: file_name.to_string(),
+            content: content.to_vec(),
+        }]),
+        Some("magnet" | "txt") => {
+            let text = std::str::from_utf8(content)
+                .map_err(|_| (StatusCode::UNPROCESSABLE_ENTITY, "Not a text file"))?;
+            let magnets = text
+                .lines()
+                .map(str::trim)
+                .filter(|line| line.starts_with("magnet:?"))
+                .map(|magnet| NewTorrent::Magnet(magnet.to_string()))
+                .collect::<Vec<_>>();
+            if magnets.is_empty() {
+                return Err((StatusCode::UNPROCESSABLE_ENTITY, "No magnet link found"));
+            }
+            Ok(magnets)
+        }
+        _ => Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Only .torrent, .magnet and .txt files can be imported",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub_server::{Recorder, serve};
+    use axum::Router;
+    use axum::body::{Body, Bytes};
+    use axum::extract::Multipart;
+    use axum::routing::post;
+    use http_body_util::channel::Channel;
+    use serde_json::json;
+    use std::time::Duration;
+
+    /// Starts a stub of the TorBox API recording the torrents created, as `<field>=<file name or
+    /// magnet>`. Magnets of the `ffff` hash are refused.
+    async fn start_torbox(created: Recorder<String>) -> Torbox {
+        let app = Router::new().route(
+            "/v1/api/torrents/createtorrent",
+            post(move |mut multipart: Multipart| async move {
+                let field = multipart.next_field().await.unwrap().unwrap();
+                let name = field.name().unwrap_or_default().to_string();
+                let value = match field.file_name() {
+                    Some(file_name) => file_name.to_string(),
+                    None => field.text().await.unwrap(),
+                };
+                if value.contains("ffff") {
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        axum::Json(json!({
+                            "success": false,
+                            "error": "DOWNLOAD_SERVER_ERROR",
+                            "detail": "Failed",
+                            "data": null,
+                        })),
+                    );
+                }
+                created.record(format!("{}={}", name, value));
+                (
+                    StatusCode::OK,
+                    axum::Json(json!({
+                        "success": true,
+                        "error": null,
+                        "detail": "Added",
+                        "data": {"hash": "AAAA"},
+                    })),
+                )
+            }),
+        );
+        Torbox::with_base_url("KEY".to_string(), serve(app).await)
+    }
+
+    async fn put(
+        path: &str,
+        content: impl Into<Body>,
+        torbox: Option<&Torbox>,
+        refresh_trigger: &Notify,
+    ) -> Response {
+        let req = Request::builder()
+            .method("PUT")
+            .body(content.into())
+            .unwrap();
+        put_handler(req, PathBuf::from(path), torbox, refresh_trigger).await
+    }
+
+    /// The body of an upload aborted by the client.
+    fn aborted_body() -> Body {
+        let (sender, body) = Channel::<Bytes, std::io::Error>::new(1);
+        sender.abort(std::io::ErrorKind::ConnectionReset.into());
+        Body::new(body)
+    }
+
+    #[tokio::test]
+    async fn it_imports_uploaded_files() {
+        let created = Recorder::default();
+        let torbox = start_torbox(created.clone()).await;
+        let refresh_trigger = Notify::new();
+
+        assert_eq!(
+            put(
+                "_import/Heat.1995.torrent",
+                "d8:announce...e",
+                Some(&torbox),
+                &refresh_trigger
+            )
+            .await
+            .status(),
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            put(
+                "_import/queue.txt",
+                "magnet:?xt=urn:btih:bbbb\n",
+                Some(&torbox),
+                &refresh_trigger
+            )
+            .await
+            .status(),
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            created.wait_for(2).await,
+            vec!["file=Heat.1995.torrent", "magnet=magnet:?xt=urn:btih:bbbb"]
+        );
+        tokio::time::timeout(Duration::ZERO, refresh_trigger.notified())
+            .await
+            .expect("a refresh was requested");
+    }
+
+    #[tokio::test]
+    async fn it_refuses_other_uploads() {
+        let created = Recorder::default();
+        let torbox = start_torbox(created.clone()).await;
+        let refresh_trigger = Notify::new();
+
+        let statuses = [
+            put(
+                "Movies/Heat.1995.torrent",
+                "...",
+                Some(&torbox),
+                &refresh_trigger,
+            )
+            .await
+            .status(),
+            put("_import/Heat.1995.torrent", "...", None, &refresh_trigger)
+                .await
+                .status(),
+            put(
+                "_import/Heat.1995.torrent",
+                vec![0u8; MAX_FILE_SIZE + 1],
+                Some(&torbox),
+                &refresh_trigger,
+            )
+            .await
+            .status(),
+            put(
+                "_import/Heat.1995.torrent",
+                aborted_body(),
+                Some(&torbox),
+                &refresh_trigger,
+            )
+            .await
+            .status(),
+            put(
+                "_import/Heat.1995.mkv",
+                "...",
+                Some(&torbox),
+                &refresh_trigger,
+            )
+            .await
+            .status(),
+            put(
+                "_import/queue.magnet",
+                "nothing here",
+                Some(&torbox),
+                &refresh_trigger,
+            )
+            .await
+            .status(),
+            put(
+                "_import/queue.magnet",
+                "magnet:?xt=urn:btih:ffff",
+                Some(&torbox),
+                &refresh_trigger,
+            )
+            .await
+            .status(),
+        ];
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::FORBIDDEN,
+                StatusCode::FORBIDDEN,
+                StatusCode::PAYLOAD_TOO_LARGE,
+                StatusCode::BAD_REQUEST,
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                StatusCode::BAD_GATEWAY,
+            ]
+        );
+        assert!(created.wait_for(0).await.is_empty());
+        assert!(
+            tokio::time::timeout(Duration::ZERO, refresh_trigger.notified())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn it_reports_the_torrents_that_failed() {
+        let created = Recorder::default();
+        let torbox = start_torbox(created.clone()).await;
+        let refresh_trigger = Notify::new();
+
+        let resp = put(
+            "_import/queue.txt",
+            "magnet:?xt=urn:btih:ffff\nmagnet:?xt=urn:btih:bbbb\n",
+            Some(&torbox),
+            &refresh_trigger,
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.starts_with("Imported 1 of 2 torrents"), "{}", body);
+        assert!(body.contains("magnet:?xt=urn:btih:ffff: "), "{}", body);
+        assert!(!body.contains("bbbb"), "{}", body);
+        // The magnet after the failed one was still added
+        assert_eq!(
+            created.wait_for(1).await,
+            vec!["magnet=magnet:?xt=urn:btih:bbbb"]
+        );
+        tokio::time::timeout(Duration::ZERO, refresh_trigger.notified())
+            .await
+            .expect("a refresh was requested");
+    }
+
+    #[test]
+    fn it_imports_to_a_torbox_account() {
+        let accounts = ["torbox:KEY:main", "real-debrid:KEY:rd"]
+            .map(|account| account.parse::<AccountConfig>().unwrap());
+        let import = |account: &str| {
+            ImportConfig {
+                account: account.to_string(),
+            }
+            .client(&accounts)
+        };
+        assert!(import("main").is_ok());
+        assert!(import("rd").is_err());
+        assert!(import("other").is_err());
+    }
+
+    #[test]
+    fn it_reads_torrent_and_magnet_files() {
+        assert_eq!(
+            read_import("Heat.1995.torrent", b"d8:announce...e"),
+            Ok(vec![NewTorrent::File {
+                name: "Heat.1995.torrent".to_string(),
+                content: b"d8:announce...e".to_vec(),
+            }])
+        );
+        assert_eq!(
+            read_import(
+                "queue.TXT",
+                b"magnet:?xt=urn:btih:aaaa\r\n# comment\n  magnet:?xt=urn:btih:bbbb\n"
+            ),
+            Ok(vec![
+                NewTorrent::Magnet("magnet:?xt=urn:btih:aaaa".to_string()),
+                NewTorrent::Magnet("magnet:?xt=urn:btih:bbbb".to_string()),
+            ])
+        );
+        assert_eq!(
+            read_import("empty.magnet", b"nothing here").unwrap_err().0,
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(
+            read_import("Heat.1995.mkv", b"...").unwrap_err().0,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+    }
+}
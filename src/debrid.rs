@@ -0,0 +1,357 @@
+use crate::real_debrid_client::RealDebrid;
+use crate::torbox_client::Torbox;
+use async_trait::async_trait;
+use clap::ValueEnum;
+use headers::HeaderValue;
+use reqwest::Response;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Debrid services Javelot can read torrents from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Provider {
+    Torbox,
+    RealDebrid,
+}
+
+impl Provider {
+    pub fn client(self, api_key: String) -> Arc<dyn DebridProvider> {
+        match self {
+            Provider::Torbox => Arc::new(Torbox::new(api_key)),
+            Provider::RealDebrid => Arc::new(RealDebrid::new(api_key)),
+        }
+    }
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => f.write_str(value.get_name()),
+            None => Err(fmt::Error),
+        }
+    }
+}
+
+/// Common interface of the debrid service clients.
+#[async_trait]
+pub trait DebridProvider: Send + Sync {
+    /// Lists the torrents whose files can currently be streamed.
+    async fn list_torrents(&self) -> anyhow::Result<Vec<DebridTorrent>>;
+
+    /// Lists every torrent of the account, those still downloading included. Their files may
+    /// be left out.
+    async fn list_downloads(&self) -> anyhow::Result<Vec<DebridTorrent>>;
+
+    /// Adds a torrent to the account, returns its info hash.
+    async fn add_torrent(&self, torrent: NewTorrent) -> anyhow::Result<String>;
+
+    /// Streams a file of a torrent, forwarding the `Range` header when there is one.
+    async fn torrent_stream(
+        &self,
+        torrent_id: &str,
+        file_id: i64,
+        range_header: Option<HeaderValue>,
+    ) -> anyhow::Result<Response>;
+}
+
+/// A torrent to add to a debrid account.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NewTorrent {
+    Magnet(String),
+    /// Content of a `.torrent` file.
+    File {
+        name: String,
+        content: Vec<u8>,
+    },
+}
+
+/// A named debrid account, each one having its own client.
+pub struct DebridAccount {
+    pub name: String,
+    pub provider: Arc<dyn DebridProvider>,
+}
+
+/// Every configured debrid account, seen as a single library.
+pub struct DebridAccounts {
+    accounts: Vec<DebridAccount>,
+}
+
+impl DebridAccounts {
+    pub fn new(accounts: Vec<DebridAccount>) -> Self {
+        DebridAccounts { accounts }
+    }
+
+    /// Finds the client of an account by its name.
+    pub fn get(&self, name: &str) -> Option<&dyn DebridProvider> {
+        self.accounts
+            .iter()
+            .find(|account| account.name == name)
+            .map(|account| account.provider.as_ref())
+    }
+
+    /// The account torrents are added to when none is picked.
+    pub fn default_account(&self) -> Option<&DebridAccount> {
+        self.accounts.first()
+    }
+
+    /// Lists the torrents of every account. A release present on several accounts (same info
+    /// hash) is only kept once, served by the first account it was found on.
+    pub async fn list_torrents(&self) -> anyhow::Result<Vec<DebridTorrent>> {
+        self.merge(|provider| provider.list_torrents()).await
+    }
+
+    /// Lists the torrents of every account, those still downloading included, collapsed the
+    /// same way.
+    pub async fn list_downloads(&self) -> anyhow::Result<Vec<DebridTorrent>> {
+        self.merge(|provider| provider.list_downloads()).await
+    }
+
+    async fn merge<'a>(
+        &'a self,
+        list: impl Fn(&'a dyn DebridProvider) -> ListFuture<'a>,
+    ) -> anyhow::Result<Vec<DebridTorrent>> {
+        let mut seen_hashes = HashSet::new();
+        let mut torrents = vec![];
+        for account in &self.accounts {
+            let account_torrents = list(account.provider.as_ref()).await.map_err(|e| {
+                e.context(format!(
+                    "Failed to list torrents of account {}",
+                    account.name
+                ))
+            })?;
+            for mut torrent in account_torrents {
+                if !seen_hashes.insert(torrent.hash.to_lowercase()) {
+                    continue;
+                }
+                torrent.account = account.name.clone();
+                torrents.push(torrent);
+            }
+        }
+        Ok(torrents)
+    }
+}
+
+type ListFuture<'a> = Pin<Box<dyn Future<Output = anyhow::Result<Vec<DebridTorrent>>> + Send + 'a>>;
+
+/// Provider agnostic view of a torrent.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DebridTorrent {
+    /// Name of the account serving the torrent.
+    pub account: String,
+    pub id: String,
+    pub hash: String,
+    pub name: Option<String>,
+    pub size: i64,
+    pub created_at: String,
+    pub updated_at: String,
+    /// Download progress between 0 and 1.
+    pub progress: f64,
+    pub download_finished: bool,
+    pub files: Vec<DebridFile>,
+}
+
+/// Provider agnostic view of a file inside a torrent.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DebridFile {
+    pub id: i64,
+    /// Path of the file inside the torrent, e.g. `Release/Sample/sample.mkv`.
+    pub name: String,
+    /// Name of the file without its directories.
+    pub short_name: String,
+    pub size: i64,
+}
+
+/// Everything needed to stream a file back from the debrid service.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct DebridFileMetadata {
+    pub(crate) account: String,
+    pub(crate) torrent_id: String,
+    pub(crate) file_id: i64,
+}
+
+/// The version of every torrent listed by a refresh, to tell which torrents changed by the next
+/// one.
+#[derive(Debug, Default)]
+pub struct TorrentVersions {
+    /// Each torrent by account and id.
+    versions: HashMap<(String, String), TorrentVersion>,
+}
+
+impl TorrentVersions {
+    pub fn new(torrents: &[DebridTorrent]) -> Self {
+        let versions = torrents
+            .iter()
+            .map(|torrent| {
+                (
+                    (torrent.account.clone(), torrent.id.clone()),
+                    TorrentVersion {
+                        account: torrent.account.clone(),
+                        id: torrent.id.clone(),
+                        name: torrent.name.clone(),
+                        hash: torrent.hash.clone(),
+                        updated_at: torrent.updated_at.clone(),
+                    },
+                )
+            })
+            .collect();
+        TorrentVersions { versions }
+    }
+
+    /// Compares the torrents listed now with these versions.
+    pub fn changes(&self, torrents: &[DebridTorrent]) -> TorrentChanges {
+        let changed = torrents
+            .iter()
+            .filter(|torrent| {
+                self.versions
+                    .get(&(torrent.account.clone(), torrent.id.clone()))
+                    .is_none_or(|version| {
+                        version.hash != torrent.hash || version.updated_at != torrent.updated_at
+                    })
+            })
+            .cloned()
+            .collect();
+        let listed = torrents
+            .iter()
+            .map(|torrent| (&torrent.account, &torrent.id))
+            .collect::<HashSet<_>>();
+        let mut removed = self
+            .versions
+            .iter()
+            .filter(|((account, id), _)| !listed.contains(&(account, id)))
+            .map(|(_, version)| version.clone())
+            .collect::<Vec<_>>();
+        removed.sort_by(|a, b| (&a.account, &a.id).cmp(&(&b.account, &b.id)));
+        TorrentChanges { changed, removed }
+    }
+}
+
+/// A torrent as listed by a refresh.
+#[derive(Debug, Clone)]
+pub struct TorrentVersion {
+    pub account: String,
+    pub id: String,
+    pub name: Option<String>,
+    hash: String,
+    updated_at: String,
+}
+
+/// Torrents added, changed or removed between two refreshes.
+#[derive(Debug)]
+pub struct TorrentChanges {
+    /// Torrents added or changed since.
+    pub changed: Vec<DebridTorrent>,
+    /// Torrents removed since.
+    pub removed: Vec<TorrentVersion>,
+}
+
+impl TorrentChanges {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct StubProvider(Vec<DebridTorrent>);
+
+    #[async_trait]
+    impl DebridProvider for StubProvider {
+        async fn list_torrents(&self) -> anyhow::Result<Vec<DebridTorrent>> {
+            Ok(self.0.clone())
+        }
+
+        async fn list_downloads(&self) -> anyhow::Result<Vec<DebridTorrent>> {
+            Ok(self.0.clone())
+        }
+
+        async fn add_torrent(&self, _torrent: NewTorrent) -> anyhow::Result<String> {
+            anyhow::bail!("not implemented")
+        }
+
+        async fn torrent_stream(
+            &self,
+            _torrent_id: &str,
+            _file_id: i64,
+            _range_header: Option<HeaderValue>,
+        ) -> anyhow::Result<Response> {
+            anyhow::bail!("not implemented")
+        }
+    }
+
+    fn torrent(id: &str, hash: &str) -> DebridTorrent {
+        DebridTorrent {
+            id: id.to_string(),
+            hash: hash.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn it_merges_accounts_and_collapses_duplicates() {
+        let accounts = DebridAccounts::new(vec![
+            DebridAccount {
+                name: "first".to_string(),
+                provider: Arc::new(StubProvider(vec![torrent("1", "aaa"), torrent("2", "bbb")])),
+            },
+            DebridAccount {
+                name: "second".to_string(),
+                provider: Arc::new(StubProvider(vec![torrent("9", "AAA"), torrent("3", "ccc")])),
+            },
+        ]);
+
+        let torrents = accounts
+            .list_torrents()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|torrent| (torrent.account, torrent.id))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            torrents,
+            vec![
+                ("first".to_string(), "1".to_string()),
+                ("first".to_string(), "2".to_string()),
+                ("second".to_string(), "3".to_string()),
+            ]
+        );
+        assert!(accounts.get("second").is_some());
+        assert!(accounts.get("third").is_none());
+    }
+
+    #[test]
+    fn it_finds_changed_torrents() {
+        let updated = |id: &str, updated_at: &str| DebridTorrent {
+            updated_at: updated_at.to_string(),
+            ..torrent(id, id)
+        };
+        let versions =
+            TorrentVersions::new(&[updated("1", "a"), updated("2", "a"), updated("3", "a")]);
+
+        let changes = versions.changes(&[updated("1", "a"), updated("2", "b"), updated("4", "a")]);
+        let changed = changes
+            .changed
+            .iter()
+            .map(|torrent| torrent.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(changed, vec!["2", "4"]);
+        let removed = changes
+            .removed
+            .iter()
+            .map(|torrent| torrent.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(removed, vec!["3"]);
+
+        assert!(
+            versions
+                .changes(&[updated("1", "a"), updated("2", "a"), updated("3", "a")])
+                .is_empty()
+        );
+    }
+}
@@ -1,7 +1,9 @@
+use crate::debrid::DebridFileMetadata;
 use anyhow::Context;
 use axum::http;
 use axum::http::StatusCode;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::path::{Component, Path, PathBuf};
 use time::OffsetDateTime;
 use urlencoding::encode;
@@ -10,6 +12,10 @@ use webdav_meta::xml::nonempty::NonEmpty;
 
 pub struct FakeFilesystem {
     files: HashMap<PathBuf, Node>,
+    /// The paths of the nodes in every folder, sorted so listings come out in order.
+    children: HashMap<PathBuf, BTreeSet<PathBuf>>,
+    /// Whether the tree comes from a snapshot and no refresh succeeded since.
+    stale: bool,
 }
 
 impl FakeFilesystem {
@@ -20,52 +26,151 @@ impl FakeFilesystem {
         });
         let mut map: HashMap<PathBuf, Node> = HashMap::new();
         map.insert(PathBuf::from("/"), root_dir);
-        FakeFilesystem { files: map }
+        FakeFilesystem {
+            files: map,
+            children: HashMap::new(),
+            stale: false,
+        }
     }
 
-    pub fn remove_node(&mut self, path: &Path) {
-        let to_delete = self
-            .files
-            .keys()
-            .filter(|k| k.starts_with(path))
-            .cloned()
-            .collect::<Vec<_>>();
-        for path_to_delete in to_delete {
-            self.files.remove(&path_to_delete);
+    /// Loads a tree saved with [`FakeFilesystem::save_snapshot`], marked as stale. Returns `None`
+    /// when there is no snapshot yet.
+    pub fn load_snapshot(path: &Path) -> anyhow::Result<Option<FakeFilesystem>> {
+        let content = match std::fs::read(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read snapshot {}", path.display()));
+            }
+        };
+        let files: HashMap<PathBuf, Node> = serde_json::from_slice(&content)
+            .with_context(|| format!("Failed to parse snapshot {}", path.display()))?;
+
+        let mut fake_fs = FakeFilesystem::new_with_root();
+        for (path, node) in files {
+            fake_fs.add_node(&path, node);
         }
+        fake_fs.stale = true;
+        Ok(Some(fake_fs))
+    }
+
+    /// Saves the tree, download details included, so it can be served right away on the next
+    /// start.
+    pub fn save_snapshot(&self, path: &Path) -> anyhow::Result<()> {
+        let content = serde_json::to_vec(&self.files)?;
+        // Write next to the snapshot then rename, so a crash never leaves a truncated one behind
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, content)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.stale
     }
 
     pub fn read_node(&self, path: &Path) -> Option<&Node> {
         self.files.get(path)
     }
 
+    /// Lists the nodes of a folder sorted by path, in `O(children)`.
     pub fn read_dir(&self, path: &Path) -> Option<Vec<(PathBuf, &Node)>> {
         if let Some(Node::Folder(_)) = self.files.get(path) {
-            let children = self.children(path);
             return Some(
-                children
-                    .into_iter()
-                    .filter_map(|child| self.read_node(&child).and_then(|node| Some((child, node))))
+                self.children(path)
+                    .filter_map(|child| self.read_node(child).map(|node| (child.clone(), node)))
                     .collect(),
             );
         }
         None
     }
 
-    fn children(&self, path: &Path) -> Vec<PathBuf> {
-        self.files
-            .keys()
-            .filter(|file_path| file_path.parent() == Some(path))
-            .map(|file_path| file_path.to_path_buf())
-            .collect()
+    fn children(&self, path: &Path) -> impl Iterator<Item = &PathBuf> {
+        self.children.get(path).into_iter().flatten()
     }
 
     pub fn add_node(&mut self, path: &Path, node: Node) {
+        if let Some(parent) = path.parent() {
+            self.children
+                .entry(parent.to_owned())
+                .or_default()
+                .insert(path.to_owned());
+        }
         self.files.insert(path.to_owned(), node);
     }
+
+    /// Lists the nodes added, removed or modified since `previous`, sorted by path. The nodes
+    /// inside an added or removed folder are left out, the folder stands for them.
+    pub fn changes_since(&self, previous: &FakeFilesystem) -> Vec<PathChange> {
+        let added = self
+            .files
+            .keys()
+            .filter(|path| !previous.files.contains_key(*path))
+            .map(PathBuf::as_path)
+            .collect::<HashSet<_>>();
+        let removed = previous
+            .files
+            .keys()
+            .filter(|path| !self.files.contains_key(*path))
+            .map(PathBuf::as_path)
+            .collect::<HashSet<_>>();
+        let is_top_most = |paths: &HashSet<&Path>, path: &Path| {
+            path.parent().is_none_or(|parent| !paths.contains(parent))
+        };
+
+        let mut changes = added
+            .iter()
+            .filter(|path| is_top_most(&added, path))
+            .map(|path| PathChange {
+                path: path.to_path_buf(),
+                kind: ChangeKind::Added,
+                folder: matches!(self.files[*path], Node::Folder(_)),
+            })
+            .chain(
+                removed
+                    .iter()
+                    .filter(|path| is_top_most(&removed, path))
+                    .map(|path| PathChange {
+                        path: path.to_path_buf(),
+                        kind: ChangeKind::Removed,
+                        folder: matches!(previous.files[*path], Node::Folder(_)),
+                    }),
+            )
+            .chain(self.files.iter().filter_map(|(path, node)| {
+                let previous_node = previous.files.get(path)?;
+                (previous_node != node).then(|| PathChange {
+                    path: path.clone(),
+                    kind: ChangeKind::Modified,
+                    folder: matches!(node, Node::Folder(_)),
+                })
+            }))
+            .collect::<Vec<_>>();
+        changes.sort();
+        changes
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
+/// A node that differs between two versions of the filesystem.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PathChange {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+    /// Whether the node is a folder, or was one before being removed.
+    pub folder: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    /// A file now pointing to another debrid file, or of another size.
+    Modified,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize)]
 pub enum Node {
     File(File),
     Folder(Folder),
@@ -122,14 +227,14 @@ impl Node {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct File {
     pub(crate) name: String,
     pub(crate) size: i64,
-    pub(crate) download_details: (i64, i64),
+    pub(crate) download_details: DebridFileMetadata,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Folder {
     pub(crate) name: String,
 }
@@ -161,16 +266,20 @@ mod tests {
             let file = Node::File(File {
                 name: "hello.txt".to_string(),
                 size: 1200,
-                download_details: (-1, -1),
+                download_details: DebridFileMetadata {
+                    account: "account".to_string(),
+                    torrent_id: "-1".to_string(),
+                    file_id: -1,
+                },
             });
-            fs.files.insert(PathBuf::from("/hello"), folder.clone());
-            fs.files.insert(PathBuf::from("/hello.txt"), file.clone());
+            fs.add_node(&PathBuf::from("/hello"), folder.clone());
+            fs.add_node(&PathBuf::from("/hello.txt"), file.clone());
             assert_eq_unordered_sort!(
-                fs.read_dir(&PathBuf::from("/")),
-                Some(vec![
+                fs.read_dir(&PathBuf::from("/")).unwrap(),
+                vec![
                     (PathBuf::from("/hello.txt"), &file),
                     (PathBuf::from("/hello"), &folder)
-                ])
+                ]
             );
         }
 
@@ -183,15 +292,143 @@ mod tests {
             let file = Node::File(File {
                 name: "hello.txt".to_string(),
                 size: 1200,
-                download_details: (-1, -1),
+                download_details: DebridFileMetadata {
+                    account: "account".to_string(),
+                    torrent_id: "-1".to_string(),
+                    file_id: -1,
+                },
             });
-            fs.files.insert(PathBuf::from("/hello"), folder.clone());
-            fs.files
-                .insert(PathBuf::from("/hello/hello.txt"), file.clone());
+            fs.add_node(&PathBuf::from("/hello"), folder.clone());
+            fs.add_node(&PathBuf::from("/hello/hello.txt"), file.clone());
             assert_eq_unordered_sort!(
-                fs.read_dir(&PathBuf::from("/hello")),
-                Some(vec![(PathBuf::from("/hello/hello.txt"), &file)])
+                fs.read_dir(&PathBuf::from("/hello")).unwrap(),
+                vec![(PathBuf::from("/hello/hello.txt"), &file)]
             );
         }
+
+        #[test]
+        fn it_lists_children_in_order() {
+            let mut fs = FakeFilesystem::new_with_root();
+            for name in ["b", "c", "a"] {
+                fs.add_node(
+                    &Path::new("/shows").join(name),
+                    Node::Folder(Folder {
+                        name: name.to_string(),
+                    }),
+                );
+            }
+            fs.add_node(
+                Path::new("/shows"),
+                Node::Folder(Folder {
+                    name: "shows".to_string(),
+                }),
+            );
+            let paths = fs
+                .read_dir(Path::new("/shows"))
+                .unwrap()
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                paths,
+                ["/shows/a", "/shows/b", "/shows/c"].map(PathBuf::from)
+            );
+            assert!(fs.read_dir(Path::new("/shows/a")).unwrap().is_empty());
+            assert_eq!(fs.read_dir(Path::new("/")).unwrap().len(), 1);
+        }
+    }
+
+    mod changes_since {
+        use super::*;
+
+        fn folder(fs: &mut FakeFilesystem, path: &str) {
+            fs.add_node(
+                Path::new(path),
+                Node::Folder(Folder {
+                    name: path.rsplit('/').next().unwrap().to_string(),
+                }),
+            );
+        }
+
+        fn file(fs: &mut FakeFilesystem, path: &str, size: i64) {
+            fs.add_node(
+                Path::new(path),
+                Node::File(File {
+                    name: path.rsplit('/').next().unwrap().to_string(),
+                    size,
+                    download_details: DebridFileMetadata::default(),
+                }),
+            );
+        }
+
+        #[test]
+        fn it_lists_top_most_changes() {
+            let mut previous = FakeFilesystem::new_with_root();
+            folder(&mut previous, "/shows");
+            folder(&mut previous, "/shows/Old");
+            file(&mut previous, "/shows/Old/e01.mkv", 1);
+            folder(&mut previous, "/shows/Kept");
+            file(&mut previous, "/shows/Kept/e01.mkv", 1);
+            file(&mut previous, "/shows/Kept/e02.mkv", 1);
+
+            let mut fs = FakeFilesystem::new_with_root();
+            folder(&mut fs, "/shows");
+            folder(&mut fs, "/shows/Kept");
+            file(&mut fs, "/shows/Kept/e01.mkv", 1);
+            file(&mut fs, "/shows/Kept/e02.mkv", 2);
+            file(&mut fs, "/shows/Kept/e03.mkv", 1);
+            folder(&mut fs, "/shows/New");
+            file(&mut fs, "/shows/New/e01.mkv", 1);
+
+            let change = |path: &str, kind, folder| PathChange {
+                path: PathBuf::from(path),
+                kind,
+                folder,
+            };
+            assert_eq!(
+                fs.changes_since(&previous),
+                vec![
+                    change("/shows/Kept/e02.mkv", ChangeKind::Modified, false),
+                    change("/shows/Kept/e03.mkv", ChangeKind::Added, false),
+                    change("/shows/New", ChangeKind::Added, true),
+                    change("/shows/Old", ChangeKind::Removed, true),
+                ]
+            );
+            assert!(fs.changes_since(&fs).is_empty());
+        }
+    }
+
+    #[test]
+    fn it_saves_and_loads_snapshots() {
+        let path =
+            std::env::temp_dir().join(format!("javelot-snapshot-{}.json", std::process::id()));
+        let mut fs = FakeFilesystem::new_with_root();
+        let folder = Node::Folder(Folder {
+            name: "shows".to_string(),
+        });
+        let file = Node::File(File {
+            name: "e01.mkv".to_string(),
+            size: 1200,
+            download_details: DebridFileMetadata {
+                account: "account".to_string(),
+                torrent_id: "1".to_string(),
+                file_id: 2,
+            },
+        });
+        fs.add_node(Path::new("/shows"), folder.clone());
+        fs.add_node(Path::new("/shows/e01.mkv"), file.clone());
+
+        assert!(FakeFilesystem::load_snapshot(&path).unwrap().is_none());
+        fs.save_snapshot(&path).unwrap();
+        let loaded = FakeFilesystem::load_snapshot(&path).unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(loaded.is_stale());
+        assert!(!fs.is_stale());
+        assert!(loaded.changes_since(&fs).is_empty());
+        assert_eq!(
+            loaded.read_dir(Path::new("/shows")).unwrap(),
+            vec![(PathBuf::from("/shows/e01.mkv"), &file)]
+        );
     }
 }
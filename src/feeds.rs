@@ -0,0 +1,413 @@
+use crate::quality::{Quality, QualityPolicy};
+use anyhow::Context;
+use async_trait::async_trait;
+use axum::Router;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use moka::future::Cache;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, warn};
+
+/// Episodes of a season feed with an unknown number of episodes are requested one after the
+/// other until one has no streams.
+const MAX_SEASON_EPISODES: u32 = 50;
+/// How long the streams of a movie or an episode are reused, TorBox polls feeds regularly.
+const STREAMS_TTL: Duration = Duration::from_secs(15 * 60);
+/// How long the number of episodes of a season is reused.
+const EPISODE_COUNT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The `[feeds]` section of the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeedsConfig {
+    /// URL of a Stremio addon serving torrents, e.g. `https://torrentio.strem.fun`.
+    pub source: String,
+    /// URL of a Stremio addon serving the episodes of the shows, Cinemeta by default.
+    #[serde(default = "default_metadata")]
+    pub metadata: String,
+}
+
+fn default_metadata() -> String {
+    "https://v3-cinemeta.strem.io".to_string()
+}
+
+impl FeedsConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for url in [&self.source, &self.metadata] {
+            reqwest::Url::parse(url).with_context(|| format!("invalid url {}", url))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MediaKind {
+    Movie,
+    Series,
+}
+
+/// A torrent offered by a stream source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stream {
+    /// Lowercase.
+    pub info_hash: String,
+    pub release_name: String,
+    /// Name of the file of the stream, when the torrent holds more than the requested media.
+    pub file_name: Option<String>,
+    pub size: i64,
+    pub trackers: Vec<String>,
+}
+
+impl Stream {
+    fn quality(&self) -> Quality {
+        Quality::parse(&format!(
+            "{}/{}",
+            self.release_name,
+            self.file_name.as_deref().unwrap_or_default()
+        ))
+    }
+
+    fn magnet(&self) -> String {
+        let mut magnet = format!(
+            "magnet:?xt=urn:btih:{}&dn={}",
+            self.info_hash,
+            urlencoding::encode(&self.release_name)
+        );
+        for tracker in &self.trackers {
+            magnet.push_str(&format!("&tr={}", urlencoding::encode(tracker)));
+        }
+        magnet
+    }
+}
+
+/// Where the releases of the feeds come from.
+#[async_trait]
+pub trait StreamSource: Send + Sync {
+    /// Lists the torrents of a movie, `tt0113277`, or of an episode, `tt0903747:1:2`.
+    async fn streams(&self, kind: MediaKind, id: &str) -> anyhow::Result<Vec<Stream>>;
+}
+
+/// Where the episodes of the season feeds come from.
+#[async_trait]
+pub trait EpisodeSource: Send + Sync {
+    /// Number of episodes of a season of a show, `tt0903747`, `None` when it is not known.
+    async fn episode_count(&self, imdb_id: &str, season: u32) -> anyhow::Result<Option<u32>>;
+}
+
+/// What a feed is about, from its file name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FeedId {
+    imdb_id: String,
+    season: Option<u32>,
+    episode: Option<u32>,
+}
+
+impl FeedId {
+    /// Parses `tt0113277` for a movie, `tt0903747:1` for a season and `tt0903747:1:2` for an
+    /// episode.
+    fn parse(id: &str) -> Option<FeedId> {
+        let mut parts = id.split(':');
+        let imdb_id = parts.next()?;
+        let digits = imdb_id.strip_prefix("tt")?;
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let season = parts.next().map(str::parse).transpose().ok()?;
+        let episode = parts.next().map(str::parse).transpose().ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(FeedId {
+            imdb_id: imdb_id.to_string(),
+            season,
+            episode,
+        })
+    }
+}
+
+/// Serves RSS feeds of the best releases of a movie, a season or an episode, with magnet links
+/// TorBox can subscribe to.
+pub struct Feeds {
+    source: Box<dyn StreamSource>,
+    episodes: Box<dyn EpisodeSource>,
+    quality_policy: QualityPolicy,
+    /// Streams of each movie or episode, by kind and id.
+    streams_cache: Cache<(MediaKind, String), Vec<Stream>>,
+    /// Number of episodes of each season, by IMDb id and season.
+    episode_count_cache: Cache<(String, u32), Option<u32>>,
+}
+
+impl Feeds {
+    pub fn new(
+        source: Box<dyn StreamSource>,
+        episodes: Box<dyn EpisodeSource>,
+        quality_policy: QualityPolicy,
+    ) -> Self {
+        Feeds {
+            source,
+            episodes,
+            quality_policy,
+            streams_cache: Cache::builder().time_to_live(STREAMS_TTL).build(),
+            episode_count_cache: Cache::builder().time_to_live(EPISODE_COUNT_TTL).build(),
+        }
+    }
+
+    pub fn router<S: Clone + Send + Sync + 'static>(self) -> Router<S> {
+        Router::new()
+            .route("/feeds/{file}", get(feed))
+            .with_state(Arc::new(self))
+    }
+
+    /// The releases picked by the quality policy for each media of the feed, a release providing
+    /// several episodes being listed once.
+    async fn releases(&self, id: &FeedId) -> anyhow::Result<Vec<Stream>> {
+        let mut releases = match (id.season, id.episode) {
+            (None, _) => self.select(MediaKind::Movie, &id.imdb_id).await?,
+            (Some(season), Some(episode)) => {
+                let episode_id = format!("{}:{}:{}", id.imdb_id, season, episode);
+                self.select(MediaKind::Series, &episode_id).await?
+            }
+            (Some(season), None) => {
+                let episode_count = self.episode_count(&id.imdb_id, season).await;
+                let mut releases = vec![];
+                for episode in 1..=episode_count.unwrap_or(MAX_SEASON_EPISODES) {
+                    let episode_id = format!("{}:{}:{}", id.imdb_id, season, episode);
+                    let episode_releases = self.select(MediaKind::Series, &episode_id).await?;
+                    // Without the number of episodes, the first one missing ends the season
+                    if episode_releases.is_empty() && episode_count.is_none() {
+                        break;
+                    }
+                    releases.extend(episode_releases);
+                }
+                releases
+            }
+        };
+        let mut seen_hashes = HashSet::new();
+        releases.retain(|release| seen_hashes.insert(release.info_hash.clone()));
+        Ok(releases)
+    }
+
+    /// The number of episodes of a season, `None` when the episode source can't tell.
+    async fn episode_count(&self, imdb_id: &str, season: u32) -> Option<u32> {
+        let episode_count = self
+            .episode_count_cache
+            .try_get_with((imdb_id.to_string(), season), async {
+                self.episodes.episode_count(imdb_id, season).await
+            })
+            .await;
+        match episode_count {
+            Ok(episode_count) => episode_count,
+            Err(e) => {
+                warn!("Failed to count episodes of {}: {:?}", imdb_id, e);
+                None
+            }
+        }
+    }
+
+    async fn select(&self, kind: MediaKind, id: &str) -> anyhow::Result<Vec<Stream>> {
+        let streams = self
+            .streams_cache
+            .try_get_with((kind, id.to_string()), async {
+                self.source.streams(kind, id).await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("{:#}", e))
+            .with_context(|| format!("Failed to list streams of {}", id))?;
+        let streams = streams
+            .into_iter()
+            .map(|stream| (stream.quality(), stream))
+            .collect();
+        Ok(self
+            .quality_policy
+            .select_candidates(streams, |(quality, stream)| (quality, stream.size))
+            .into_iter()
+            .map(|(_, stream)| stream)
+            .collect())
+    }
+}
+
+async fn feed(State(feeds): State<Arc<Feeds>>, Path(file): Path<String>) -> Response {
+    let Some(id) = file.strip_suffix(".rss").and_then(FeedId::parse) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match feeds.releases(&id).await {
+        Ok(releases) => (
+            [(CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
+            rss(&file, &id, &releases),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to build feed {}: {:?}", file, e);
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    }
+}
+
+/// RSS 2.0 document listing the releases, each one linking to its magnet.
+fn rss(file: &str, id: &FeedId, releases: &[Stream]) -> String {
+    let mut rss = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    rss.push_str("<rss version=\"2.0\">\n<channel>\n");
+    rss.push_str(&format!("<title>Javelot {}</title>\n", escape(file)));
+    rss.push_str(&format!(
+        "<link>https://www.imdb.com/title/{}/</link>\n",
+        escape(&id.imdb_id)
+    ));
+    rss.push_str("<description>Releases picked by the quality policy</description>\n");
+    for release in releases {
+        let magnet = escape(&release.magnet());
+        rss.push_str(&format!(
+            "<item><title>{}</title><link>{}</link><guid isPermaLink=\"false\">{}</guid>\
+             <enclosure url=\"{}\" length=\"{}\" type=\"application/x-bittorrent\"/></item>\n",
+            escape(&release.release_name),
+            magnet,
+            release.info_hash,
+            magnet,
+            release.size
+        ));
+    }
+    rss.push_str("</channel>\n</rss>\n");
+    rss
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stremio::StremioAddon;
+    use crate::stub_server::{Recorder, serve};
+    use serde_json::json;
+
+    /// Serves the first season of a show with three episodes, a season pack listed for the first
+    /// and the last ones and no streams for the second one, records the streams requested.
+    async fn start_fixture_server(requests: Recorder<String>) -> String {
+        let season_pack = json!({
+            "name": "Torrentio\n1080p",
+            "title": "Show.S01.1080p.WEB-DL.x264-NTb\n👤 40 💾 9 GB ⚙️ Source",
+            "infoHash": "CCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCC",
+            "fileIdx": 0,
+            "sources": ["tracker:udp://tracker.example:1337/announce", "dht:cccc"],
+            "behaviorHints": {"filename": "Show.S01E01.1080p.WEB-DL.x264-NTb.mkv", "videoSize": 1500000000},
+        });
+        let stream = |title: &str, hash: &str| json!({"title": title, "infoHash": hash});
+        let episode_1 = json!({"streams": [
+            stream("Show.S01E01.720p.HDTV.x264-KILLERS\n💾 500 MB", "aaaa"),
+            stream("Show.S01E01.1080p.BluRay.x264-YIFY\n💾 2 GB", "bbbb"),
+            season_pack.clone(),
+            {"title": "Direct link", "url": "https://example.com/show.mkv"},
+        ]});
+        let episode_3 = json!({"streams": [
+            season_pack,
+            stream("Show.S01E03.1080p.BluRay.x264-GROUP\n💾 2.5 GB", "dddd"),
+        ]});
+        let meta = json!({"meta": {"id": "tt0903747", "videos": [
+            {"season": 0, "episode": 7},
+            {"season": 1, "episode": 1},
+            {"season": 1, "episode": 2},
+            {"season": 1, "episode": 3},
+            {"season": 2, "episode": 1},
+        ]}});
+
+        let app = Router::new()
+            .route(
+                "/stream/series/{file}",
+                get(move |Path(file): Path<String>| async move {
+                    requests.record(file.clone());
+                    axum::Json(match file.as_str() {
+                        "tt0903747:1:1.json" => episode_1,
+                        "tt0903747:1:3.json" => episode_3,
+                        _ => json!({"streams": []}),
+                    })
+                }),
+            )
+            .route(
+                "/meta/series/tt0903747.json",
+                get(move || async move { axum::Json(meta) }),
+            );
+        format!("{}/manifest.json", serve(app).await)
+    }
+
+    async fn start_feeds(source: String) -> String {
+        let quality_policy = QualityPolicy {
+            resolutions: vec!["1080p".to_string(), "720p".to_string()],
+            blocked_groups: vec!["YIFY".to_string()],
+            ..Default::default()
+        };
+        let feeds = Feeds::new(
+            Box::new(StremioAddon::new(source.clone())),
+            Box::new(StremioAddon::new(source)),
+            quality_policy,
+        );
+        format!("{}/feeds", serve(feeds.router()).await)
+    }
+
+    #[tokio::test]
+    async fn it_serves_the_best_releases_of_a_season() {
+        let requests = Recorder::default();
+        let url = start_feeds(start_fixture_server(requests.clone()).await).await;
+
+        let resp = reqwest::get(format!("{}/tt0903747:1.rss", url))
+            .await
+            .unwrap();
+        assert_eq!(
+            resp.headers()[CONTENT_TYPE],
+            "application/rss+xml; charset=utf-8"
+        );
+        let rss = resp.text().await.unwrap();
+        let items = rss
+            .lines()
+            .filter(|line| line.starts_with("<item>"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            items,
+            vec![
+                "<item><title>Show.S01.1080p.WEB-DL.x264-NTb</title>\
+                <link>magnet:?xt=urn:btih:cccccccccccccccccccccccccccccccccccccccc&amp;dn=Show.S01.1080p.WEB-DL.x264-NTb&amp;tr=udp%3A%2F%2Ftracker.example%3A1337%2Fannounce</link>\
+                <guid isPermaLink=\"false\">cccccccccccccccccccccccccccccccccccccccc</guid>\
+                <enclosure url=\"magnet:?xt=urn:btih:cccccccccccccccccccccccccccccccccccccccc&amp;dn=Show.S01.1080p.WEB-DL.x264-NTb&amp;tr=udp%3A%2F%2Ftracker.example%3A1337%2Fannounce\" \
+                length=\"1500000000\" type=\"application/x-bittorrent\"/></item>",
+                "<item><title>Show.S01E03.1080p.BluRay.x264-GROUP</title>\
+                <link>magnet:?xt=urn:btih:dddd&amp;dn=Show.S01E03.1080p.BluRay.x264-GROUP</link>\
+                <guid isPermaLink=\"false\">dddd</guid>\
+                <enclosure url=\"magnet:?xt=urn:btih:dddd&amp;dn=Show.S01E03.1080p.BluRay.x264-GROUP\" \
+                length=\"2684354560\" type=\"application/x-bittorrent\"/></item>",
+            ]
+        );
+
+        let episode = reqwest::get(format!("{}/tt0903747:1:1.rss", url))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(episode.matches("<item>").count(), 1);
+        // The season was read up to its last episode, the episode again came from the cache
+        assert_eq!(
+            requests.wait_for(3).await,
+            vec![
+                "tt0903747:1:1.json",
+                "tt0903747:1:2.json",
+                "tt0903747:1:3.json"
+            ]
+        );
+        reqwest::get(format!("{}/tt0903747:1.rss", url))
+            .await
+            .unwrap();
+        assert_eq!(requests.wait_for(3).await.len(), 3);
+        for invalid in ["tt0903747.xml", "show.rss", "tt0903747:1:2:3.rss"] {
+            let resp = reqwest::get(format!("{}/{}", url, invalid)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        }
+    }
+}
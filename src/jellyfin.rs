@@ -0,0 +1,59 @@
+use crate::fake_file_system::{ChangeKind, PathChange};
+use crate::media_server::{MediaServer, MediaServerConfig};
+use serde::Serialize;
+use tracing::info;
+
+/// Posts the changed paths to the `/Library/Media/Updated` endpoint shared by Jellyfin and Emby.
+pub struct Jellyfin {
+    config: MediaServerConfig,
+    client: reqwest::Client,
+}
+
+impl Jellyfin {
+    pub fn new(config: MediaServerConfig, client: reqwest::Client) -> Self {
+        Jellyfin { config, client }
+    }
+}
+
+impl MediaServer for Jellyfin {
+    fn update_requests(&self, changes: &[PathChange]) -> Vec<reqwest::RequestBuilder> {
+        let updates = changes
+            .iter()
+            .map(|change| MediaUpdate {
+                path: self.config.mounted_path(&change.path),
+                update_type: match change.kind {
+                    ChangeKind::Added => "Created",
+                    ChangeKind::Removed => "Deleted",
+                    ChangeKind::Modified => "Modified",
+                },
+            })
+            .collect::<Vec<_>>();
+        info!(
+            updates = updates.len(),
+            message = "Notifying media server of updates"
+        );
+        let url = format!(
+            "{}/Library/Media/Updated",
+            self.config.url.trim_end_matches('/')
+        );
+        vec![
+            self.client
+                .request(reqwest::Method::POST, url)
+                .header("X-Emby-Token", &self.config.token)
+                .json(&MediaUpdates { updates }),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct MediaUpdates {
+    updates: Vec<MediaUpdate>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct MediaUpdate {
+    path: String,
+    update_type: &'static str,
+}
@@ -0,0 +1,177 @@
+use crate::anime::parse_anime_from_torrents;
+use crate::debrid::{DebridFileMetadata, DebridTorrent};
+use crate::movies::{Movie, parse_movies_from_torrents};
+use crate::overrides::Overrides;
+use crate::quality::Quality;
+use crate::shows::{Show, ShowEpisode, group_episodes, parse_shows_from_torrents};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Show libraries episodes can be exposed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShowLibrary {
+    Shows,
+    Anime,
+}
+
+/// What a debrid file has been identified as.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MediaItem {
+    Episode {
+        library: ShowLibrary,
+        show: String,
+        season: i32,
+        episode: ShowEpisode,
+    },
+    Movie(Movie),
+}
+
+/// Every media exposed by the virtual filesystem, with every release of each of them. See
+/// [`QualityPolicy`](crate::quality::QualityPolicy) for picking the exposed ones.
+#[derive(Debug, Default)]
+pub struct Library {
+    pub shows: Vec<Show>,
+    pub anime: Vec<Show>,
+    pub movies: Vec<Movie>,
+}
+
+impl Library {
+    /// Builds the library out of the files currently present on the debrid accounts, using the
+    /// media item recorded for each of them. Files without a media item are left out.
+    pub fn from_media_items(
+        torrents: &[DebridTorrent],
+        media_items: &HashMap<DebridFileMetadata, MediaItem>,
+    ) -> Library {
+        let mut shows = vec![];
+        let mut anime = vec![];
+        let mut movies = vec![];
+
+        for torrent in torrents {
+            for file in &torrent.files {
+                let debrid_file_metadata = DebridFileMetadata {
+                    account: torrent.account.clone(),
+                    torrent_id: torrent.id.clone(),
+                    file_id: file.id,
+                };
+                let Some(media_item) = media_items.get(&debrid_file_metadata) else {
+                    continue;
+                };
+
+                match media_item.clone() {
+                    MediaItem::Episode {
+                        library,
+                        show,
+                        season,
+                        mut episode,
+                    } => {
+                        episode.debrid_file_metadata = debrid_file_metadata;
+                        episode.size = file.size;
+                        episode.quality = Quality::parse(&file.name);
+                        match library {
+                            ShowLibrary::Shows => shows.push((show, season, episode)),
+                            ShowLibrary::Anime => anime.push((show, season, episode)),
+                        }
+                    }
+                    MediaItem::Movie(mut movie) => {
+                        movie.debrid_file_metadata = debrid_file_metadata;
+                        movie.size = file.size;
+                        movie.quality = Quality::parse(&file.name);
+                        movies.push(movie);
+                    }
+                }
+            }
+        }
+
+        Library {
+            shows: group_episodes(shows),
+            anime: group_episodes(anime),
+            movies,
+        }
+    }
+}
+
+/// What the parsers found in the torrents.
+#[derive(Debug, Default)]
+pub struct ParsedMediaItems {
+    /// Media items found by the parsers on their own.
+    pub parsed: Vec<(DebridFileMetadata, MediaItem)>,
+    /// Media items found with the help of an override.
+    pub overridden: Vec<(DebridFileMetadata, MediaItem)>,
+    /// Files ignored by an override.
+    pub ignored: Vec<DebridFileMetadata>,
+    /// Torrents the parsers went through, by account and torrent id.
+    pub torrents: Vec<(String, String)>,
+}
+
+/// Runs every parser over the torrents and returns what each recognized file is.
+pub fn parse_media_items(
+    torrents: &[DebridTorrent],
+    overrides: &Overrides,
+) -> anyhow::Result<ParsedMediaItems> {
+    let shows = parse_shows_from_torrents(torrents.to_vec(), overrides)?;
+    let anime = parse_anime_from_torrents(torrents.to_vec(), overrides)?;
+    let movies = parse_movies_from_torrents(torrents.to_vec(), overrides)?;
+
+    let mut media_items = episode_items(shows, ShowLibrary::Shows);
+    media_items.extend(episode_items(anime, ShowLibrary::Anime));
+    media_items.extend(
+        movies
+            .into_iter()
+            .map(|movie| (movie.debrid_file_metadata.clone(), MediaItem::Movie(movie))),
+    );
+
+    let mut overridden_files = HashSet::new();
+    let mut ignored = vec![];
+    for torrent in torrents {
+        for file in &torrent.files {
+            let Some(file_override) = overrides.find(torrent, file) else {
+                continue;
+            };
+            let debrid_file_metadata = DebridFileMetadata {
+                account: torrent.account.clone(),
+                torrent_id: torrent.id.clone(),
+                file_id: file.id,
+            };
+            if file_override.ignore {
+                ignored.push(debrid_file_metadata);
+            } else {
+                overridden_files.insert(debrid_file_metadata);
+            }
+        }
+    }
+
+    let (overridden, parsed) = media_items
+        .into_iter()
+        .partition(|(debrid_file_metadata, _)| overridden_files.contains(debrid_file_metadata));
+    Ok(ParsedMediaItems {
+        parsed,
+        overridden,
+        ignored,
+        torrents: torrents
+            .iter()
+            .map(|torrent| (torrent.account.clone(), torrent.id.clone()))
+            .collect(),
+    })
+}
+
+fn episode_items(shows: Vec<Show>, library: ShowLibrary) -> Vec<(DebridFileMetadata, MediaItem)> {
+    let mut media_items = vec![];
+    for show in shows {
+        for season in show.seasons.into_values() {
+            for episode in season.episodes {
+                media_items.push((
+                    episode.debrid_file_metadata.clone(),
+                    MediaItem::Episode {
+                        library,
+                        show: show.title.clone(),
+                        season: season.number,
+                        episode,
+                    },
+                ));
+            }
+        }
+    }
+    media_items
+}
@@ -1,27 +1,98 @@
+mod anime;
+mod backfill;
 mod cli;
+mod config;
+mod content_database;
 mod dav_server;
+mod debrid;
 mod fake_file_system;
+mod feeds;
+mod jellyfin;
+mod library;
+mod media_server;
+mod metadata_provider;
+mod movies;
+mod naming;
+mod overrides;
+mod plex;
+mod qbittorrent;
+mod quality;
+mod real_debrid_client;
+mod retry;
 mod shows;
+mod stremio;
+#[cfg(test)]
+mod stub_server;
 mod torbox_client;
+mod webhooks;
 
-use crate::cli::Cli;
-use crate::dav_server::webdav_handler;
-use crate::fake_file_system::{FakeFilesystem, File, Folder, Node};
-use crate::shows::parse_shows_from_torrents;
+use crate::backfill::{backfill, build_report};
+use crate::cli::{Cli, Command};
+use crate::config::Config;
+use crate::content_database::ContentDatabase;
+use crate::dav_server::{IMPORT_FOLDER, webdav_handler};
+use crate::debrid::{
+    DebridAccount, DebridAccounts, DebridFileMetadata, DebridTorrent, TorrentVersions,
+};
+use crate::fake_file_system::{FakeFilesystem, File, Folder, Node, PathChange};
+use crate::feeds::Feeds;
+use crate::library::{Library, MediaItem, parse_media_items};
+use crate::media_server::MediaServerNotifier;
+use crate::metadata_provider::MetadataProvider;
+use crate::movies::Movie;
+use crate::naming::Naming;
+use crate::overrides::{Overrides, OverridesFile};
+use crate::qbittorrent::QBittorrent;
+use crate::quality::version_labels;
+use crate::shows::Show;
+use crate::stremio::StremioAddon;
 use crate::torbox_client::Torbox;
+use crate::webhooks::{LibraryEvent, Webhook, path_events};
 use anyhow::Context;
+use arc_swap::ArcSwap;
 use axum::Router;
 use axum::routing::any;
 use clap::Parser;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time;
-use tracing::info;
+use tokio::sync::Notify;
+use tracing::{error, info, warn};
 
 #[derive(Clone)]
 struct AppState {
-    fake_file_system: Arc<Mutex<FakeFilesystem>>,
-    torbox_client: Arc<Torbox>,
+    config: Arc<Config>,
+    /// The published tree, replaced as a whole by each refresh so readers never see a half-built one.
+    fake_file_system: Arc<ArcSwap<FakeFilesystem>>,
+    debrid_accounts: Arc<DebridAccounts>,
+    content_database: Arc<ContentDatabase>,
+    overrides_file: Arc<OverridesFile>,
+    metadata_provider: Option<Arc<MetadataProvider>>,
+    /// Wakes the refresh job up before its next scheduled run.
+    refresh_trigger: Arc<Notify>,
+    /// Told about the paths changed by each refresh.
+    media_server_notifiers: Arc<Vec<MediaServerNotifier>>,
+    /// Told about the events of each refresh.
+    webhooks: Arc<Vec<Webhook>>,
+    /// Where the filesystem is saved after each refresh.
+    snapshot_path: Arc<PathBuf>,
+    /// What the last refresh was built from, `None` until a refresh succeeds.
+    last_refresh: Arc<tokio::sync::Mutex<Option<LastRefresh>>>,
+    /// Folder of each torrent in the published tree, empty until a refresh succeeds.
+    torrent_folders: Arc<ArcSwap<TorrentFolders>>,
+    /// TorBox account the files of the import folder are added to, `None` without `[import]`.
+    import_client: Option<Arc<Torbox>>,
+}
+
+/// Path of the folder of each torrent under the torrents root, by account and torrent id.
+type TorrentFolders = HashMap<(String, String), PathBuf>;
+
+/// What a refresh was built from, to only redo the work made necessary by the next one.
+struct LastRefresh {
+    torrents: TorrentVersions,
+    overrides: Arc<Overrides>,
+    media_items: HashMap<DebridFileMetadata, MediaItem>,
 }
 
 #[tokio::main]
@@ -30,21 +101,120 @@ async fn main() -> anyhow::Result<()> {
 
     let cli = Cli::parse();
 
-    let fake_fs = FakeFilesystem::new_with_root();
+    let config = Config::load(cli.config_path.as_deref())?;
+
+    let content_database = ContentDatabase::open(&cli.database_path)?;
+
+    let overrides_file = OverridesFile::load(cli.overrides_path.clone())?;
+
+    let metadata_provider = cli
+        .metadata_api_key
+        .clone()
+        .map(|api_key| {
+            MetadataProvider::new(
+                api_key,
+                cli.metadata_api_url.clone(),
+                cli.metadata_cache_path.clone(),
+            )
+        })
+        .transpose()?;
+
+    let account_configs = cli.account_configs()?;
+    let import_client = config
+        .import
+        .as_ref()
+        .map(|import| import.client(&account_configs))
+        .transpose()?;
+    let debrid_accounts = account_configs
+        .into_iter()
+        .map(|config| DebridAccount {
+            name: config.name.unwrap_or_default(),
+            provider: config.provider.client(config.api_key),
+        })
+        .collect();
+    let debrid_accounts = DebridAccounts::new(debrid_accounts);
+
+    if let Some(Command::Backfill) = cli.command {
+        let report = backfill(
+            &debrid_accounts,
+            &content_database,
+            &overrides_file.current(),
+        )
+        .await?;
+        report.print();
+        return Ok(());
+    }
+
+    // Serve the last known library until the first refresh completes, rather than an empty one
+    let fake_fs = match FakeFilesystem::load_snapshot(&cli.snapshot_path) {
+        Ok(Some(fake_fs)) => {
+            info!("Serving the filesystem snapshot until the first refresh");
+            fake_fs
+        }
+        Ok(None) => FakeFilesystem::new_with_root(),
+        Err(e) => {
+            warn!("Ignoring filesystem snapshot: {:?}", e);
+            FakeFilesystem::new_with_root()
+        }
+    };
 
-    let torbox_client = Torbox::new(cli.api_key.clone());
+    let media_server_notifiers = config
+        .media_servers
+        .iter()
+        .cloned()
+        .map(MediaServerNotifier::start)
+        .collect();
+    let webhooks = config
+        .webhooks
+        .iter()
+        .cloned()
+        .map(Webhook::start)
+        .collect::<anyhow::Result<_>>()?;
 
     let app_state = AppState {
-        fake_file_system: Arc::new(Mutex::new(fake_fs)),
-        torbox_client: Arc::new(torbox_client),
+        config: Arc::new(config),
+        fake_file_system: Arc::new(ArcSwap::from_pointee(fake_fs)),
+        debrid_accounts: Arc::new(debrid_accounts),
+        content_database: Arc::new(content_database),
+        overrides_file: Arc::new(overrides_file),
+        metadata_provider: metadata_provider.map(Arc::new),
+        refresh_trigger: Arc::new(Notify::new()),
+        media_server_notifiers: Arc::new(media_server_notifiers),
+        webhooks: Arc::new(webhooks),
+        snapshot_path: Arc::new(cli.snapshot_path.clone()),
+        last_refresh: Arc::new(tokio::sync::Mutex::new(None)),
+        torrent_folders: Arc::new(ArcSwap::from_pointee(TorrentFolders::new())),
+        import_client: import_client.map(Arc::new),
     };
 
     start_refresh_job(app_state.clone(), cli.refresh_interval).await;
+    start_overrides_watch_job(app_state.clone());
 
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/", any(webdav_handler))
-        .route("/{*path}", any(webdav_handler))
-        .with_state(app_state);
+        .route("/{*path}", any(webdav_handler));
+    if let Some(download_client) = &app_state.config.download_client {
+        let qbittorrent = QBittorrent::new(
+            download_client.clone(),
+            app_state.config.naming.torrents_root.clone(),
+            app_state.debrid_accounts.clone(),
+            app_state.content_database.clone(),
+            app_state.torrent_folders.clone(),
+            app_state.refresh_trigger.clone(),
+        )?;
+        app = app.merge(qbittorrent.router());
+        info!("Serving the download client API");
+    }
+    if let Some(feeds) = &app_state.config.feeds {
+        let feeds = Feeds::new(
+            Box::new(StremioAddon::new(feeds.source.clone())),
+            Box::new(StremioAddon::new(feeds.metadata.clone())),
+            app_state.config.quality.clone(),
+        );
+        app = app.merge(feeds.router());
+        info!("Serving release feeds");
+    }
+    let app = app.with_state(app_state);
 
     // run our app with hyper, listening globally on port 3000
     let listener = tokio::net::TcpListener::bind(cli.address).await?;
@@ -58,55 +228,410 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn refresh_filesystem(app_state: AppState) -> anyhow::Result<()> {
+/// Refreshes the filesystem out of the torrents currently on the debrid accounts. Only the
+/// torrents added or changed since the last refresh are parsed, and the tree is left as is when
+/// nothing changed. Otherwise the tree is rebuilt as a whole out of the recorded mappings, as the
+/// release picked for an episode and the folder names depend on the files of every torrent.
+///
+/// Returns what changed.
+async fn refresh_filesystem(app_state: AppState) -> anyhow::Result<RefreshReport> {
     info!("Refreshing filesystem...");
 
-    let torrents = app_state.torbox_client.list_torrents().await?;
-    let shows = parse_shows_from_torrents(torrents)?;
+    // Held for the whole refresh, so refreshes don't overlap
+    let mut last_refresh = app_state.last_refresh.lock().await;
+
+    let torrents = app_state.debrid_accounts.list_torrents().await?;
+
+    // New files get the mapping found by the parsers, known files keep their recorded one. A
+    // change of the overrides can affect any file.
+    let overrides = app_state.overrides_file.current();
+    let changes = last_refresh
+        .as_ref()
+        .map(|last_refresh| last_refresh.torrents.changes(&torrents));
+    let overrides_changed = last_refresh
+        .as_ref()
+        .is_none_or(|last_refresh| !Arc::ptr_eq(&last_refresh.overrides, &overrides));
+    let torrents_to_parse = match &changes {
+        Some(changes) if !overrides_changed => &changes.changed[..],
+        _ => &torrents[..],
+    };
+    let parsed_media_items = parse_media_items(torrents_to_parse, &overrides)?;
+    let changed_mappings = app_state.content_database.record(&parsed_media_items)?;
+    // Mappings of the torrents deleted from the accounts, while running or not
+    let removed_mappings = app_state.content_database.prune(&torrents)?;
+    info!(
+        parsed_torrents = torrents_to_parse.len(),
+        removed_torrents = changes.as_ref().map_or(0, |changes| changes.removed.len()),
+        changed_mappings,
+        removed_mappings,
+        message = "Recorded file mappings"
+    );
+
+    // Mappings can also be fixed by hand in the database
+    let media_items = app_state.content_database.media_items()?;
+    if let (Some(changes), Some(last_refresh)) = (&changes, &*last_refresh)
+        && !overrides_changed
+        && changes.is_empty()
+        && last_refresh.media_items == media_items
+    {
+        info!("Filesystem is up to date");
+        return Ok(RefreshReport::default());
+    }
+
+    let mut events = vec![];
+    for torrent in changes.iter().flat_map(|changes| &changes.removed) {
+        events.push(LibraryEvent::TorrentRemoved {
+            account: torrent.account.clone(),
+            torrent: torrent.name.clone().unwrap_or_else(|| torrent.id.clone()),
+        });
+    }
+    let ignored = parsed_media_items.ignored.iter().cloned().collect();
+    let unclassified = build_report(torrents_to_parse, &media_items, &ignored, changed_mappings)
+        .unclassified
+        .into_iter()
+        .map(|(account, torrent_name, file_name)| {
+            format!("[{}] {}: {}", account, torrent_name, file_name)
+        })
+        .collect::<Vec<_>>();
+    if !unclassified.is_empty() {
+        events.push(LibraryEvent::ParseFailures {
+            files: unclassified,
+        });
+    }
+
+    let mut library = Library::from_media_items(&torrents, &media_items);
+    if let Some(metadata_provider) = &app_state.metadata_provider {
+        library.shows = metadata_provider.canonicalize_shows(library.shows).await;
+        library.anime = metadata_provider.canonicalize_shows(library.anime).await;
+    }
+    let quality_policy = &app_state.config.quality;
+    library.shows = quality_policy.select_releases(library.shows);
+    library.anime = quality_policy.select_releases(library.anime);
+    library.movies = quality_policy.select_movies(library.movies);
+
+    // Build the new tree off to the side, requests keep being served from the current one
+    let mut fake_fs = FakeFilesystem::new_with_root();
+
+    let naming = &app_state.config.naming;
+    let multi_version = quality_policy.multi_version;
+    add_shows(
+        &mut fake_fs,
+        &Path::new("/").join(&naming.shows_root),
+        library.shows,
+        naming,
+        multi_version,
+    );
+    add_shows(
+        &mut fake_fs,
+        &Path::new("/").join(&naming.anime_root),
+        library.anime,
+        naming,
+        multi_version,
+    );
+    add_movies(
+        &mut fake_fs,
+        &Path::new("/").join(&naming.movies_root),
+        library.movies,
+        naming,
+        multi_version,
+    );
+    let torrent_folders = add_torrents(
+        &mut fake_fs,
+        &Path::new("/").join(&naming.torrents_root),
+        &torrents,
+    );
+    if app_state.import_client.is_some() {
+        add_root(&mut fake_fs, Path::new(IMPORT_FOLDER));
+    }
+
+    let path_changes = fake_fs.changes_since(&app_state.fake_file_system.load());
+    events.extend(path_events(&path_changes, &fake_fs, naming));
+    let fake_fs = Arc::new(fake_fs);
+    app_state.fake_file_system.store(fake_fs.clone());
+    app_state.torrent_folders.store(Arc::new(torrent_folders));
+    *last_refresh = Some(LastRefresh {
+        torrents: TorrentVersions::new(&torrents),
+        overrides,
+        media_items,
+    });
+
+    let snapshot_path = app_state.snapshot_path.clone();
+    let saved = tokio::task::spawn_blocking(move || fake_fs.save_snapshot(&snapshot_path)).await;
+    if let Err(e) = saved.map_err(anyhow::Error::from).flatten() {
+        error!("Failed to save filesystem snapshot: {:?}", e);
+    }
+
+    info!(
+        changed_paths = path_changes.len(),
+        message = "Filesystem refresh completed"
+    );
+    Ok(RefreshReport {
+        path_changes,
+        events,
+    })
+}
 
-    // Lock the filesystem for updating
-    let mut fake_fs = app_state.fake_file_system.lock().unwrap();
+/// What a refresh changed.
+#[derive(Debug, Default)]
+struct RefreshReport {
+    path_changes: Vec<PathChange>,
+    events: Vec<LibraryEvent>,
+}
 
-    // Reset shows directory
-    fake_fs.remove_node(&PathBuf::from("/shows"));
+/// Adds an empty folder at `root`.
+fn add_root(fake_fs: &mut FakeFilesystem, root: &Path) {
     fake_fs.add_node(
-        &PathBuf::from("/shows"),
+        root,
         Node::Folder(Folder {
-            name: "shows".to_string(),
+            name: root
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
         }),
     );
+}
+
+/// Adds a folder at `root` holding a `<show>/<season>/<episode>` tree. With `multi_version`
+/// the releases of an episode are named after their quality so Plex offers them as versions.
+fn add_shows(
+    fake_fs: &mut FakeFilesystem,
+    root: &Path,
+    shows: Vec<Show>,
+    naming: &Naming,
+    multi_version: bool,
+) {
+    add_root(fake_fs, root);
 
-    // Add all shows again
-    for show in shows {
-        let path = PathBuf::from(&format!("/shows/{}", &show.title));
-        fake_fs.add_node(&path, Node::Folder(Folder { name: show.title }));
+    let show_names = unique_folder_names(
+        shows
+            .iter()
+            .map(|show| {
+                (
+                    naming.show_folder(&show.title),
+                    show.title.replace('/', "-"),
+                )
+            })
+            .collect(),
+    );
+    for (show, show_name) in shows.into_iter().zip(show_names) {
+        let path = root.join(&show_name);
+        fake_fs.add_node(&path, Node::Folder(Folder { name: show_name }));
 
         for season in show.seasons.values() {
-            let season_name = format!("Season {}", season.number);
+            let season_name = naming.season_folder(season);
             let season_folder = PathBuf::from(&season_name);
             let season_path = path.join(season_folder);
             fake_fs.add_node(&season_path, Node::Folder(Folder { name: season_name }));
 
-            for episode in &season.episodes {
-                let episode_folder = PathBuf::from(&episode.file_name);
-                let episode_path = season_path.join(episode_folder);
-                fake_fs.add_node(
-                    &episode_path,
-                    Node::File(File {
-                        name: episode.file_name.clone(),
-                        size: episode.size,
-                        download_details: (
-                            episode.torbox_file_metadata.torrent_id,
-                            episode.torbox_file_metadata.file_id,
-                        ),
-                    }),
+            // The releases of an episode are next to each other
+            for releases in season.episodes.chunk_by(|a, b| a.numbers == b.numbers) {
+                let labels = version_labels(
+                    releases
+                        .iter()
+                        .map(|episode| (&episode.quality, &episode.debrid_file_metadata)),
+                );
+                let mut file_names = releases
+                    .iter()
+                    .zip(&labels)
+                    .map(|(episode, label)| {
+                        naming.episode_file(&show.title, season.number, episode, label)
+                    })
+                    .collect::<Vec<_>>();
+                if (multi_version && !naming.episode_file_has_quality(&releases[0]))
+                    || has_duplicates(&file_names)
+                {
+                    file_names = releases
+                        .iter()
+                        .zip(&labels)
+                        .map(|(episode, label)| {
+                            episode.version_file_name(&show.title, season.number, label)
+                        })
+                        .collect();
+                }
+                for (episode, file_name) in releases.iter().zip(file_names) {
+                    fake_fs.add_node(
+                        &season_path.join(&file_name),
+                        Node::File(File {
+                            name: file_name,
+                            size: episode.size,
+                            download_details: episode.debrid_file_metadata.clone(),
+                        }),
+                    )
+                }
+            }
+        }
+    }
+}
+
+/// Adds a folder at `root` holding a `<movie>/<file>` tree, the releases of a movie being
+/// next to each other.
+fn add_movies(
+    fake_fs: &mut FakeFilesystem,
+    root: &Path,
+    movies: Vec<Movie>,
+    naming: &Naming,
+    multi_version: bool,
+) {
+    add_root(fake_fs, root);
+
+    let movies = movies
+        .chunk_by(|a, b| a.display_name() == b.display_name())
+        .collect::<Vec<_>>();
+    let movie_names = unique_folder_names(
+        movies
+            .iter()
+            .map(|releases| {
+                (
+                    naming.movie_folder(&releases[0]),
+                    releases[0].display_name().replace('/', "-"),
                 )
+            })
+            .collect(),
+    );
+    for (releases, movie_name) in movies.into_iter().zip(movie_names) {
+        let path = root.join(&movie_name);
+        fake_fs.add_node(&path, Node::Folder(Folder { name: movie_name }));
+
+        let labels = version_labels(
+            releases
+                .iter()
+                .map(|movie| (&movie.quality, &movie.debrid_file_metadata)),
+        );
+        let mut file_names = releases
+            .iter()
+            .zip(&labels)
+            .map(|(movie, label)| naming.movie_file(movie, label))
+            .collect::<Vec<_>>();
+        if (multi_version && !naming.movie_file_has_quality()) || has_duplicates(&file_names) {
+            file_names = releases
+                .iter()
+                .zip(&labels)
+                .map(|(movie, label)| movie.version_file_name(label))
+                .collect();
+        }
+        for (movie, file_name) in releases.iter().zip(file_names) {
+            fake_fs.add_node(
+                &path.join(&file_name),
+                Node::File(File {
+                    name: file_name,
+                    size: movie.size,
+                    download_details: movie.debrid_file_metadata.clone(),
+                }),
+            );
+        }
+    }
+}
+
+/// Adds a folder at `root` holding a `<torrent>/<path inside the torrent>` tree of every file,
+/// whether the parsers understood it or not. Returns the folder of each torrent.
+fn add_torrents(
+    fake_fs: &mut FakeFilesystem,
+    root: &Path,
+    torrents: &[DebridTorrent],
+) -> TorrentFolders {
+    add_root(fake_fs, root);
+
+    let mut torrent_folders = TorrentFolders::new();
+    for (torrent, torrent_name) in torrent_folder_names(torrents) {
+        let name = torrent.name.as_deref().unwrap_or(&torrent.id);
+        let torrent_path = root.join(&torrent_name);
+        torrent_folders.insert(
+            (torrent.account.clone(), torrent.id.clone()),
+            torrent_path.clone(),
+        );
+        fake_fs.add_node(&torrent_path, Node::Folder(Folder { name: torrent_name }));
+
+        let mut folders = HashSet::new();
+        for file in &torrent.files {
+            let mut components = file
+                .name
+                .split('/')
+                .filter(|component| !matches!(*component, "" | "." | ".."))
+                .collect::<Vec<_>>();
+            // Most debrid services put the files in a folder named after the torrent
+            if components.len() > 1 && components[0] == name {
+                components.remove(0);
+            }
+            let Some((file_name, folder_names)) = components.split_last() else {
+                continue;
+            };
+
+            let mut path = torrent_path.clone();
+            for folder_name in folder_names {
+                path.push(folder_name);
+                if folders.insert(path.clone()) {
+                    fake_fs.add_node(
+                        &path,
+                        Node::Folder(Folder {
+                            name: folder_name.to_string(),
+                        }),
+                    );
+                }
             }
+            fake_fs.add_node(
+                &path.join(file_name),
+                Node::File(File {
+                    name: file_name.to_string(),
+                    size: file.size,
+                    download_details: DebridFileMetadata {
+                        account: torrent.account.clone(),
+                        torrent_id: torrent.id.clone(),
+                        file_id: file.id,
+                    },
+                }),
+            );
         }
     }
+    torrent_folders
+}
 
-    info!("Filesystem refresh completed");
-    Ok(())
+/// Names the folder of each torrent under the torrents root, after the torrent itself.
+fn torrent_folder_names(torrents: &[DebridTorrent]) -> Vec<(&DebridTorrent, String)> {
+    // Sorted so torrents sharing a name are told apart the same way on every refresh
+    let mut torrents = torrents.iter().collect::<Vec<_>>();
+    torrents.sort_by_key(|torrent| (&torrent.name, &torrent.account, &torrent.id));
+
+    let mut torrent_names = HashSet::new();
+    torrents
+        .into_iter()
+        .map(|torrent| {
+            let name = torrent.name.as_deref().unwrap_or(&torrent.id);
+            let mut torrent_name = name.replace('/', "-");
+            if !torrent_names.insert(torrent_name.clone()) {
+                torrent_name = format!("{} [{} {}]", torrent_name, torrent.account, torrent.id);
+                torrent_names.insert(torrent_name.clone());
+            }
+            (torrent, torrent_name)
+        })
+        .collect()
+}
+
+/// Picks the folder name of each show or movie, given as `(name, fallback)`. Names the template
+/// gives to several of them, e.g. `{title}` for two movies of different years, are replaced by
+/// their fallback, which is unique.
+fn unique_folder_names(names: Vec<(String, String)>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let clashing = names
+        .iter()
+        .filter(|(name, _)| !seen.insert(name))
+        .map(|(name, _)| name.clone())
+        .collect::<HashSet<_>>();
+    names
+        .into_iter()
+        .map(|(name, fallback)| {
+            if clashing.contains(&name) {
+                fallback
+            } else {
+                name
+            }
+        })
+        .collect()
+}
+
+/// Alternate releases can share a file name, they are then named as versions.
+fn has_duplicates(file_names: &[String]) -> bool {
+    let mut seen = HashSet::new();
+    !file_names.iter().all(|file_name| seen.insert(file_name))
 }
 
 async fn start_refresh_job(app_state: AppState, refresh_interval: u64) {
@@ -115,9 +640,26 @@ async fn start_refresh_job(app_state: AppState, refresh_interval: u64) {
 
     tokio::spawn(async move {
         loop {
-            interval.tick().await;
-            if let Err(e) = refresh_filesystem(app_state.clone()).await {
-                tracing::error!("Failed to refresh filesystem: {:?}", e);
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = app_state.refresh_trigger.notified() => {}
+            }
+            let events = match refresh_filesystem(app_state.clone()).await {
+                Ok(report) => {
+                    for notifier in app_state.media_server_notifiers.iter() {
+                        notifier.notify(&report.path_changes);
+                    }
+                    report.events
+                }
+                Err(e) => {
+                    error!("Failed to refresh filesystem: {:?}", e);
+                    vec![LibraryEvent::RefreshFailed {
+                        error: format!("{:#}", e),
+                    }]
+                }
+            };
+            for webhook in app_state.webhooks.iter() {
+                webhook.send(&events);
             }
         }
     });
@@ -127,3 +669,124 @@ async fn start_refresh_job(app_state: AppState, refresh_interval: u64) {
         message = "Started filesystem refresh job"
     );
 }
+
+/// Reloads the overrides file when it changes, and refreshes the filesystem to apply them.
+fn start_overrides_watch_job(app_state: AppState) {
+    let mut interval = tokio::time::interval(time::Duration::from_secs(5));
+
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+            match app_state.overrides_file.reload_if_changed() {
+                Ok(true) => app_state.refresh_trigger.notify_one(),
+                Ok(false) => {}
+                Err(e) => error!("Failed to reload overrides: {:?}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debrid::DebridFile;
+
+    fn torrent(account: &str, id: &str, name: &str, files: &[&str]) -> DebridTorrent {
+        DebridTorrent {
+            account: account.to_string(),
+            id: id.to_string(),
+            name: Some(name.to_string()),
+            files: files
+                .iter()
+                .enumerate()
+                .map(|(id, name)| DebridFile {
+                    id: id as i64,
+                    name: name.to_string(),
+                    short_name: name.rsplit('/').next().unwrap().to_string(),
+                    size: 1,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_mirrors_torrents_as_is() {
+        let mut fake_fs = FakeFilesystem::new_with_root();
+        add_torrents(
+            &mut fake_fs,
+            Path::new("/torrents"),
+            &[
+                torrent(
+                    "torbox-1",
+                    "1",
+                    "Show.S01",
+                    &["Show.S01/Extras/Making.Of.mkv", "Show.S01/Show.S01E01.mkv"],
+                ),
+                torrent("torbox-1", "2", "Show.S01", &["Show.S01E01.mkv"]),
+            ],
+        );
+
+        let mut paths = fake_fs
+            .read_dir(Path::new("/torrents/Show.S01"))
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/torrents/Show.S01/Extras"),
+                PathBuf::from("/torrents/Show.S01/Show.S01E01.mkv"),
+            ]
+        );
+        assert!(
+            fake_fs
+                .read_node(Path::new("/torrents/Show.S01/Extras/Making.Of.mkv"))
+                .is_some()
+        );
+        assert!(
+            fake_fs
+                .read_node(Path::new("/torrents/Show.S01 [torbox-1 2]/Show.S01E01.mkv"))
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn it_tells_apart_movies_sharing_a_folder_name() {
+        let movie = |title: &str, year: i32, file_id: i64| Movie {
+            title: title.to_string(),
+            year: Some(year),
+            debrid_file_metadata: DebridFileMetadata {
+                file_id,
+                ..Default::default()
+            },
+            size: 1,
+            quality: Default::default(),
+            extension: "mkv".to_string(),
+        };
+        let naming = toml::from_str::<Naming>("movie_folder = \"{title}\"").unwrap();
+        let mut fake_fs = FakeFilesystem::new_with_root();
+        add_movies(
+            &mut fake_fs,
+            Path::new("/movies"),
+            vec![
+                movie("Heat", 1986, 1),
+                movie("Heat", 1995, 2),
+                movie("Ronin", 1998, 3),
+            ],
+            &naming,
+            false,
+        );
+
+        for path in [
+            "/movies/Heat (1986)/Heat (1986).mkv",
+            "/movies/Heat (1995)/Heat (1995).mkv",
+            "/movies/Ronin/Ronin (1998).mkv",
+        ] {
+            assert!(fake_fs.read_node(Path::new(path)).is_some(), "{}", path);
+        }
+        assert!(fake_fs.read_node(Path::new("/movies/Heat")).is_none());
+    }
+}
@@ -0,0 +1,331 @@
+use crate::fake_file_system::PathChange;
+use crate::jellyfin::Jellyfin;
+use crate::naming::Naming;
+use crate::plex::Plex;
+use crate::retry::{RETRY_DELAY, send_with_retries};
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaServerKind {
+    Plex,
+    Jellyfin,
+    Emby,
+}
+
+/// An entry of the `[[media_servers]]` array of the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MediaServerConfig {
+    pub kind: MediaServerKind,
+    /// e.g. `http://127.0.0.1:32400`
+    pub url: String,
+    /// Plex token, or Jellyfin/Emby API key.
+    pub token: String,
+    /// Where the WebDAV server is mounted on the media server, e.g. `/mnt/javelot`.
+    pub mount_path: String,
+    /// Library section id of each root folder, e.g. `shows = 1`. Plex only.
+    #[serde(default)]
+    pub sections: HashMap<String, u32>,
+    /// Changes are sent once no refresh brought new ones for that long.
+    #[serde(default = "default_debounce_seconds")]
+    pub debounce_seconds: u64,
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+pub(crate) fn default_debounce_seconds() -> u64 {
+    30
+}
+
+pub(crate) fn default_retries() -> u32 {
+    3
+}
+
+impl MediaServerConfig {
+    pub fn validate(&self, naming: &Naming) -> anyhow::Result<()> {
+        reqwest::Url::parse(&self.url).with_context(|| format!("invalid url {}", self.url))?;
+        match self.kind {
+            MediaServerKind::Plex if self.sections.is_empty() => {
+                anyhow::bail!("sections are required by Plex")
+            }
+            MediaServerKind::Jellyfin | MediaServerKind::Emby if !self.sections.is_empty() => {
+                anyhow::bail!("sections only apply to Plex")
+            }
+            _ => {}
+        }
+        let roots = naming.roots();
+        for root in self.sections.keys() {
+            if !roots.contains(&root.as_str()) {
+                anyhow::bail!(
+                    "unknown root {} in sections, expected one of {:?}",
+                    root,
+                    roots
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Path of a node of the filesystem on the media server.
+    pub fn mounted_path(&self, path: &Path) -> String {
+        format!(
+            "{}/{}",
+            self.mount_path.trim_end_matches('/'),
+            path.strip_prefix("/").unwrap_or(path).display()
+        )
+    }
+}
+
+/// A media server to tell about the changes of the library, so it doesn't wait for its scheduled
+/// scan to pick them up.
+pub trait MediaServer: Send + Sync {
+    /// Builds the requests telling the media server about the changes, none when the changes
+    /// don't concern it.
+    fn update_requests(&self, changes: &[PathChange]) -> Vec<reqwest::RequestBuilder>;
+}
+
+/// Tells a media server about the paths changed by the refreshes, once they settle.
+#[derive(Clone)]
+pub struct MediaServerNotifier {
+    sender: mpsc::UnboundedSender<Vec<PathChange>>,
+}
+
+impl MediaServerNotifier {
+    pub fn start(config: MediaServerConfig) -> MediaServerNotifier {
+        MediaServerNotifier::spawn(config, RETRY_DELAY)
+    }
+
+    fn spawn(config: MediaServerConfig, retry_delay: Duration) -> MediaServerNotifier {
+        let client = reqwest::Client::new();
+        let media_server: Box<dyn MediaServer> = match config.kind {
+            MediaServerKind::Plex => Box::new(Plex::new(config.clone(), client)),
+            MediaServerKind::Jellyfin | MediaServerKind::Emby => {
+                Box::new(Jellyfin::new(config.clone(), client))
+            }
+        };
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(
+            NotifierTask {
+                media_server,
+                debounce: Duration::from_secs(config.debounce_seconds),
+                retries: config.retries,
+                retry_delay,
+            }
+            .run(receiver),
+        );
+        MediaServerNotifier { sender }
+    }
+
+    /// Queues the changes of a refresh.
+    pub fn notify(&self, changes: &[PathChange]) {
+        if !changes.is_empty() && self.sender.send(changes.to_vec()).is_err() {
+            error!("Media server notifications stopped");
+        }
+    }
+}
+
+struct NotifierTask {
+    media_server: Box<dyn MediaServer>,
+    debounce: Duration,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl NotifierTask {
+    async fn run(self, mut receiver: mpsc::UnboundedReceiver<Vec<PathChange>>) {
+        while let Some(changes) = receiver.recv().await {
+            let mut changes = BTreeSet::from_iter(changes);
+            while let Ok(Some(more_changes)) =
+                tokio::time::timeout(self.debounce, receiver.recv()).await
+            {
+                changes.extend(more_changes);
+            }
+
+            let changes = changes.into_iter().collect::<Vec<_>>();
+            for request in self.media_server.update_requests(&changes) {
+                if let Err(e) = send_with_retries(request, self.retries, self.retry_delay).await {
+                    error!("Failed to notify media server: {:?}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_file_system::ChangeKind;
+    use crate::stub_server::{Recorder, serve};
+    use axum::Router;
+    use axum::extract::{Query, State};
+    use axum::http::{HeaderMap, StatusCode, Uri};
+    use axum::routing::{get, post};
+    use serde_json::{Value, json};
+    use std::path::PathBuf;
+
+    /// Path, token and body of each request.
+    type Requests = Recorder<(String, String, Value)>;
+
+    /// Serves the Plex and Jellyfin APIs, failing the first request. Records every request.
+    async fn start_stub_server() -> (String, Requests) {
+        async fn record(
+            requests: &Requests,
+            uri: Uri,
+            token: Option<&str>,
+            body: Value,
+        ) -> StatusCode {
+            let count = requests.record((
+                uri.path().to_string(),
+                token.unwrap_or_default().to_string(),
+                body,
+            ));
+            if count == 1 {
+                StatusCode::SERVICE_UNAVAILABLE
+            } else {
+                StatusCode::OK
+            }
+        }
+
+        let requests = Requests::default();
+        let app = Router::new()
+            .route(
+                "/library/sections/{section}/refresh",
+                get(
+                    |State(requests): State<Requests>,
+                     Query(query): Query<HashMap<String, String>>,
+                     uri: Uri,
+                     headers: HeaderMap| async move {
+                        let token = headers["x-plex-token"].to_str().ok();
+                        record(&requests, uri, token, json!(query["path"])).await
+                    },
+                ),
+            )
+            .route(
+                "/Library/Media/Updated",
+                post(
+                    |State(requests): State<Requests>,
+                     uri: Uri,
+                     headers: HeaderMap,
+                     axum::Json(body): axum::Json<Value>| async move {
+                        let token = headers["x-emby-token"].to_str().ok();
+                        record(&requests, uri, token, body).await
+                    },
+                ),
+            )
+            .with_state(requests.clone());
+        (serve(app).await, requests)
+    }
+
+    fn change(path: &str, kind: ChangeKind, folder: bool) -> PathChange {
+        PathChange {
+            path: PathBuf::from(path),
+            kind,
+            folder,
+        }
+    }
+
+    fn config(kind: MediaServerKind, url: String, sections: &[(&str, u32)]) -> MediaServerConfig {
+        MediaServerConfig {
+            kind,
+            url,
+            token: "token".to_string(),
+            mount_path: "/mnt/javelot/".to_string(),
+            sections: sections
+                .iter()
+                .map(|(root, section)| (root.to_string(), *section))
+                .collect(),
+            debounce_seconds: 0,
+            retries: 1,
+        }
+    }
+
+    async fn recorded_requests(requests: &Requests, count: usize) -> Vec<(String, Value)> {
+        requests
+            .wait_for(count)
+            .await
+            .iter()
+            .map(|(path, token, body)| {
+                assert_eq!(token, "token");
+                (path.clone(), body.clone())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn it_asks_plex_to_scan_changed_folders() {
+        let (url, requests) = start_stub_server().await;
+        let notifier = MediaServerNotifier::spawn(
+            config(MediaServerKind::Plex, url, &[("shows", 1), ("movies", 2)]),
+            Duration::from_millis(10),
+        );
+
+        notifier.notify(&[
+            change(
+                "/shows/Show/Season 1/Show - s01e02.mkv",
+                ChangeKind::Added,
+                false,
+            ),
+            change(
+                "/shows/Show/Season 1/Show - s01e03.mkv",
+                ChangeKind::Modified,
+                false,
+            ),
+            change("/shows/Show/Season 2/Extras", ChangeKind::Added, true),
+            change("/shows/Other/Season 1", ChangeKind::Removed, true),
+            change("/movies/Heat (1995)", ChangeKind::Added, true),
+            change("/torrents/Heat.1995.mkv", ChangeKind::Added, false),
+        ]);
+
+        let scan = |section: u32, path: &str| {
+            (
+                format!("/library/sections/{}/refresh", section),
+                json!(path),
+            )
+        };
+        assert_eq!(
+            recorded_requests(&requests, 5).await,
+            vec![
+                scan(2, "/mnt/javelot/movies/Heat (1995)"),
+                scan(2, "/mnt/javelot/movies/Heat (1995)"),
+                scan(1, "/mnt/javelot/shows/Other"),
+                scan(1, "/mnt/javelot/shows/Show/Season 1"),
+                scan(1, "/mnt/javelot/shows/Show/Season 2"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_posts_changed_paths_to_jellyfin() {
+        let (url, requests) = start_stub_server().await;
+        let notifier =
+            MediaServerNotifier::spawn(config(MediaServerKind::Jellyfin, url, &[]), Duration::ZERO);
+
+        notifier.notify(&[change(
+            "/shows/Show/Season 1/Show - s01e02.mkv",
+            ChangeKind::Added,
+            false,
+        )]);
+        notifier.notify(&[change("/movies/Heat (1995)", ChangeKind::Removed, true)]);
+
+        let updates = json!({
+            "Updates": [
+                {"Path": "/mnt/javelot/movies/Heat (1995)", "UpdateType": "Deleted"},
+                {"Path": "/mnt/javelot/shows/Show/Season 1/Show - s01e02.mkv", "UpdateType": "Created"},
+            ]
+        });
+        assert_eq!(
+            recorded_requests(&requests, 2).await,
+            vec![
+                ("/Library/Media/Updated".to_string(), updates.clone()),
+                ("/Library/Media/Updated".to_string(), updates),
+            ]
+        );
+    }
+}
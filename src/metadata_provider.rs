@@ -0,0 +1,323 @@
+use crate::shows::{Show, group_episodes};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use time::OffsetDateTime;
+use tracing::{debug, info, warn};
+
+/// Titles nothing was found for are looked up again after this many seconds.
+const MISS_RETRY_DELAY: i64 = 60 * 60 * 24;
+
+/// Resolves parsed show titles to their canonical name, year and ids using a TMDB compatible
+/// API. Lookups are cached on disk.
+pub struct MetadataProvider {
+    api_key: String,
+    base_url: String,
+    client: reqwest::Client,
+    cache_path: PathBuf,
+    cache: Mutex<HashMap<String, CachedLookup>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShowMetadata {
+    pub name: String,
+    pub year: Option<i32>,
+    pub tmdb_id: i64,
+    pub tvdb_id: Option<i64>,
+}
+
+impl ShowMetadata {
+    /// Plex style folder name, e.g. `Show Name (2019) {tvdb-12345}`.
+    pub fn folder_name(&self) -> String {
+        let mut folder_name = self.name.replace('/', " ");
+        if let Some(year) = self.year {
+            folder_name.push_str(&format!(" ({})", year));
+        }
+        match self.tvdb_id {
+            Some(tvdb_id) => folder_name.push_str(&format!(" {{tvdb-{}}}", tvdb_id)),
+            None => folder_name.push_str(&format!(" {{tmdb-{}}}", self.tmdb_id)),
+        }
+        folder_name
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedLookup {
+    metadata: Option<ShowMetadata>,
+    looked_up_at: i64,
+}
+
+impl MetadataProvider {
+    pub fn new(api_key: String, base_url: String, cache_path: PathBuf) -> anyhow::Result<Self> {
+        let cache = match std::fs::read_to_string(&cache_path) {
+            Ok(content) => serde_json::from_str(&content).with_context(|| {
+                format!("Failed to parse metadata cache {}", cache_path.display())
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to read metadata cache {}", cache_path.display())
+                });
+            }
+        };
+        Ok(MetadataProvider {
+            api_key,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+            cache_path,
+            cache: Mutex::new(cache),
+        })
+    }
+
+    /// Renames shows after their canonical folder name, merging the shows resolving to the same
+    /// one. Shows that cannot be resolved keep their parsed title.
+    pub async fn canonicalize_shows(&self, shows: Vec<Show>) -> Vec<Show> {
+        let mut episodes = vec![];
+        for show in shows {
+            let title = match self.lookup_show(&show.title).await {
+                Ok(Some(metadata)) => metadata.folder_name(),
+                Ok(None) => show.title,
+                Err(e) => {
+                    warn!("Failed to look up show {}: {:?}", show.title, e);
+                    show.title
+                }
+            };
+            for season in show.seasons.into_values() {
+                for episode in season.episodes {
+                    episodes.push((title.clone(), season.number, episode));
+                }
+            }
+        }
+
+        if let Err(e) = self.save_cache() {
+            warn!("Failed to save metadata cache: {:?}", e);
+        }
+        group_episodes(episodes)
+    }
+
+    pub async fn lookup_show(&self, title: &str) -> anyhow::Result<Option<ShowMetadata>> {
+        let key = normalize_title(title);
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        if let Some(cached) = self.cache.lock().unwrap().get(&key)
+            && (cached.metadata.is_some() || now - cached.looked_up_at < MISS_RETRY_DELAY)
+        {
+            return Ok(cached.metadata.clone());
+        }
+
+        info!("Looking up show {}", title);
+        let metadata = self.search_show(title).await?;
+        self.cache.lock().unwrap().insert(
+            key,
+            CachedLookup {
+                metadata: metadata.clone(),
+                looked_up_at: now,
+            },
+        );
+        Ok(metadata)
+    }
+
+    async fn search_show(&self, title: &str) -> anyhow::Result<Option<ShowMetadata>> {
+        let url = format!("{}/3/search/tv", self.base_url);
+        let request = self
+            .client
+            .request(reqwest::Method::GET, url)
+            .query(&[("api_key", self.api_key.as_str()), ("query", title)]);
+        let resp = request.send().await.context("Failed to send request")?;
+        if !resp.status().is_success() {
+            anyhow::bail!("Request failed: {}", resp.status());
+        }
+        let json = resp
+            .json::<SearchResponse>()
+            .await
+            .context("Failed to parse json")?;
+
+        // Only an exact match is trusted, never the most popular result
+        let normalized_title = normalize_title(title);
+        let Some(result) = json
+            .results
+            .iter()
+            .find(|result| normalize_title(&result.name) == normalized_title)
+        else {
+            debug!(
+                "No show named {} among {} results",
+                title,
+                json.results.len()
+            );
+            return Ok(None);
+        };
+
+        let url = format!("{}/3/tv/{}/external_ids", self.base_url, result.id);
+        let request = self
+            .client
+            .request(reqwest::Method::GET, url)
+            .query(&[("api_key", self.api_key.as_str())]);
+        let resp = request.send().await.context("Failed to send request")?;
+        if !resp.status().is_success() {
+            anyhow::bail!("Request failed: {}", resp.status());
+        }
+        let external_ids = resp
+            .json::<ExternalIdsResponse>()
+            .await
+            .context("Failed to parse json")?;
+
+        Ok(Some(ShowMetadata {
+            name: result.name.clone(),
+            year: result
+                .first_air_date
+                .as_deref()
+                .and_then(|date| date.get(..4))
+                .and_then(|year| year.parse().ok()),
+            tmdb_id: result.id,
+            tvdb_id: external_ids.tvdb_id,
+        }))
+    }
+
+    fn save_cache(&self) -> anyhow::Result<()> {
+        let content = serde_json::to_string(&*self.cache.lock().unwrap())?;
+        // Write next to the cache then rename, so a crash never leaves a truncated cache behind
+        let tmp_path = self.cache_path.with_extension("tmp");
+        std::fs::write(&tmp_path, content)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, &self.cache_path)
+            .with_context(|| format!("Failed to write {}", self.cache_path.display()))?;
+        Ok(())
+    }
+}
+
+/// Makes titles differing only by casing or punctuation share the same cache entry.
+fn normalize_title(title: &str) -> String {
+    title
+        .chars()
+        .filter(|c| c.is_alphanumeric() || c.is_whitespace())
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SearchResponse {
+    results: Vec<SearchResult>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SearchResult {
+    id: i64,
+    name: String,
+    #[serde(rename = "first_air_date")]
+    first_air_date: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ExternalIdsResponse {
+    #[serde(rename = "tvdb_id")]
+    tvdb_id: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shows::ShowEpisode;
+    use crate::stub_server::{Recorder, serve};
+    use axum::Router;
+    use axum::extract::{Query, State};
+    use axum::routing::get;
+    use serde_json::{Value, json};
+
+    /// Serves a minimal TMDB API knowing a single show, records the searched titles.
+    async fn start_stub_server(searches: Recorder<String>) -> String {
+        let app = Router::new()
+            .route(
+                "/3/search/tv",
+                get(
+                    |State(searches): State<Recorder<String>>,
+                     Query(query): Query<HashMap<String, String>>| async move {
+                        searches.record(query["query"].clone());
+                        let results = if normalize_title(&query["query"]) == "the office us" {
+                            json!([
+                                {"id": 1, "name": "The Office", "first_air_date": "2001-07-09"},
+                                {"id": 2316, "name": "The Office (US)", "first_air_date": "2005-03-24"},
+                            ])
+                        } else {
+                            // A popular show that is not the one looked up
+                            json!([{"id": 1, "name": "The Office", "first_air_date": "2001-07-09"}])
+                        };
+                        axum::Json(json!({ "results": results }))
+                    },
+                ),
+            )
+            .route(
+                "/3/tv/2316/external_ids",
+                get(|| async { axum::Json::<Value>(json!({ "tvdb_id": 73244 })) }),
+            )
+            .with_state(searches);
+        serve(app).await
+    }
+
+    fn show(title: &str, episode: i32) -> Show {
+        group_episodes(vec![(
+            title.to_string(),
+            1,
+            ShowEpisode {
+                numbers: episode..=episode,
+                air_date: None,
+                debrid_file_metadata: Default::default(),
+                size: 0,
+                quality: Default::default(),
+                file_name: format!("{} {}.mkv", title, episode),
+            },
+        )])
+        .remove(0)
+    }
+
+    #[tokio::test]
+    async fn it_merges_shows_under_their_canonical_name() {
+        let searches = Recorder::default();
+        let base_url = start_stub_server(searches.clone()).await;
+        let cache_path =
+            std::env::temp_dir().join(format!("javelot-metadata-{}.json", std::process::id()));
+        let provider =
+            MetadataProvider::new("key".to_string(), base_url, cache_path.clone()).unwrap();
+
+        let shows = provider
+            .canonicalize_shows(vec![
+                show("The Office US", 1),
+                show("the office (us)", 2),
+                show("Unknown Show", 1),
+            ])
+            .await;
+
+        let mut titles = shows
+            .iter()
+            .map(|show| (show.title.clone(), show.seasons[&1].episodes.len()))
+            .collect::<Vec<_>>();
+        titles.sort();
+        assert_eq!(
+            titles,
+            vec![
+                ("The Office (US) (2005) {tvdb-73244}".to_string(), 2),
+                ("Unknown Show".to_string(), 1),
+            ]
+        );
+        assert_eq!(searches.wait_for(2).await.len(), 2);
+
+        // A new provider reads the lookups back from the disk cache
+        let provider = MetadataProvider::new(
+            "key".to_string(),
+            "http://0.0.0.0:1".to_string(),
+            cache_path.clone(),
+        )
+        .unwrap();
+        let metadata = provider
+            .lookup_show("The Office US")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata.tvdb_id, Some(73244));
+
+        std::fs::remove_file(cache_path).unwrap();
+    }
+}
@@ -0,0 +1,321 @@
+use crate::anime::parse_anime_episode;
+use crate::debrid::{DebridFile, DebridFileMetadata, DebridTorrent};
+use crate::overrides::Overrides;
+use crate::quality::Quality;
+use crate::shows::parse_air_date;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use torrent_name_parser::Metadata;
+
+const VIDEO_EXTENSIONS: [&str; 8] = ["mkv", "mp4", "avi", "m4v", "mov", "wmv", "ts", "m2ts"];
+
+/// Words that mark a file as bonus material rather than the main feature, when they name one of
+/// its folders or end its name.
+const EXTRA_MARKERS: [&str; 8] = [
+    "sample",
+    "trailer",
+    "extras",
+    "featurette",
+    "behind the scenes",
+    "deleted scenes",
+    "interview",
+    "bonus",
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Movie {
+    pub title: String,
+    pub year: Option<i32>,
+    /// Not persisted with the movie mapping, it is the key the mapping is stored under.
+    #[serde(skip)]
+    pub debrid_file_metadata: DebridFileMetadata,
+    #[serde(skip)]
+    pub size: i64,
+    /// Parsed from the name of the file on every refresh rather than persisted.
+    #[serde(skip)]
+    pub quality: Quality,
+    pub extension: String,
+}
+
+impl Movie {
+    /// Plex style name shared by the movie folder and its file, e.g. `Heat (1995)`.
+    pub fn display_name(&self) -> String {
+        match self.year {
+            Some(year) => format!("{} ({})", self.title, year),
+            None => self.title.clone(),
+        }
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("{}.{}", self.display_name(), self.extension)
+    }
+
+    /// Name of the file as a Plex version, e.g. `Heat (1995) - [2160p HDR].mkv`.
+    pub fn version_file_name(&self, label: &str) -> String {
+        format!("{} - [{}].{}", self.display_name(), label, self.extension)
+    }
+}
+
+/// Finds the movie of every torrent. A movie provided by several torrents is returned once for
+/// each of them, see [`QualityPolicy::select_movies`](crate::quality::QualityPolicy::select_movies).
+pub fn parse_movies_from_torrents(
+    torrents: Vec<DebridTorrent>,
+    overrides: &Overrides,
+) -> anyhow::Result<Vec<Movie>> {
+    let mut movies = vec![];
+
+    for torrent in &torrents {
+        let release_name = torrent.name.as_deref().unwrap_or_default();
+        let Some(main_feature) = pick_main_feature(torrent, overrides) else {
+            continue;
+        };
+        let Some(extension) = video_extension(&main_feature.name) else {
+            continue;
+        };
+
+        // Release names carry the title and year far more reliably than the file names inside
+        // a multi-file torrent, so only fall back to the file name when the torrent has no name.
+        let metadata = match Metadata::from(release_name) {
+            Ok(metadata) if !release_name.is_empty() => metadata,
+            _ => match Metadata::from(&main_feature.short_name) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            },
+        };
+        if metadata.is_show() || metadata.title().is_empty() {
+            continue;
+        }
+        let title = overrides
+            .find(torrent, main_feature)
+            .and_then(|file_override| file_override.title.clone())
+            .unwrap_or_else(|| metadata.title().to_string());
+
+        movies.push(Movie {
+            title,
+            year: metadata.year(),
+            size: main_feature.size,
+            quality: Quality::parse(&main_feature.name),
+            extension: extension.to_string(),
+            debrid_file_metadata: DebridFileMetadata {
+                account: torrent.account.clone(),
+                torrent_id: torrent.id.clone(),
+                file_id: main_feature.id,
+            },
+        });
+    }
+
+    Ok(movies)
+}
+
+/// Picks the file holding the feature itself out of a torrent, skipping samples, extras and
+/// episodes, daily ones included. When several candidates remain (e.g. multiple editions) the
+/// largest one wins, with the file id as a tie-breaker so the choice is stable across refreshes.
+fn pick_main_feature<'a>(
+    torrent: &'a DebridTorrent,
+    overrides: &Overrides,
+) -> Option<&'a DebridFile> {
+    torrent
+        .files
+        .iter()
+        .filter(|file| {
+            overrides
+                .find(torrent, file)
+                .is_none_or(|file_override| !file_override.ignore && !file_override.is_episode())
+        })
+        .filter(|file| video_extension(&file.name).is_some())
+        .filter(|file| !is_extra(&file.name))
+        .filter(|file| parse_anime_episode(&file.short_name).is_none())
+        .filter(|file| parse_air_date(&file.short_name).is_none())
+        .filter(|file| {
+            Metadata::from(&file.short_name)
+                .map(|metadata| !metadata.is_show())
+                .unwrap_or(true)
+        })
+        .min_by_key(|file| (Reverse(file.size), file.id))
+}
+
+pub fn video_extension(file_name: &str) -> Option<&str> {
+    let (_, extension) = file_name.rsplit_once('.')?;
+    VIDEO_EXTENSIONS
+        .iter()
+        .find(|video_extension| video_extension.eq_ignore_ascii_case(extension))
+        .copied()
+}
+
+/// Tells bonus material by a folder named after a marker, e.g. `Extras/`, or by a marker ending
+/// the file name, e.g. `heat-sample.mkv`. A marker within a title, e.g. `The.Interview.2014`,
+/// doesn't count.
+fn is_extra(file_name: &str) -> bool {
+    let file_name = file_name.to_lowercase();
+    let (folders, file) = file_name.rsplit_once('/').unwrap_or(("", &file_name));
+    let stem = file.rsplit_once('.').map_or(file, |(stem, _)| stem);
+    let stem = words(stem);
+    folders.split('/').any(|folder| is_marker(&words(folder)))
+        || is_marker(&stem)
+        || stem
+            .match_indices(' ')
+            .any(|(index, _)| is_marker(&stem[index + 1..]))
+}
+
+/// The words of a name, `Behind.The-Scenes` giving `behind the scenes`.
+fn words(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Whether words are a marker, in the singular or the plural, e.g. `deleted scenes` or `samples`.
+fn is_marker(words: &str) -> bool {
+    EXTRA_MARKERS.iter().any(|marker| {
+        words
+            .strip_prefix(marker)
+            .is_some_and(|rest| rest.is_empty() || rest == "s")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(id: i64, name: &str, size: i64) -> DebridFile {
+        DebridFile {
+            id,
+            name: name.to_string(),
+            short_name: name.rsplit('/').next().unwrap().to_string(),
+            size,
+        }
+    }
+
+    fn torrent(id: &str, name: &str, files: Vec<DebridFile>) -> DebridTorrent {
+        DebridTorrent {
+            id: id.to_string(),
+            name: Some(name.to_string()),
+            files,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_names_movies_after_the_release() {
+        let movies = parse_movies_from_torrents(
+            vec![torrent(
+                "1",
+                "Heat.1995.1080p.BluRay.x264-GROUP",
+                vec![file(
+                    10,
+                    "Heat.1995.1080p.BluRay.x264-GROUP/Heat.1995.1080p.BluRay.x264-GROUP.mkv",
+                    8_000,
+                )],
+            )],
+            &Overrides::default(),
+        )
+        .unwrap();
+
+        assert_eq!(movies.len(), 1);
+        assert_eq!(movies[0].display_name(), "Heat (1995)");
+        assert_eq!(movies[0].file_name(), "Heat (1995).mkv");
+    }
+
+    #[test]
+    fn it_picks_the_main_feature() {
+        let movies = parse_movies_from_torrents(
+            vec![torrent(
+                "1",
+                "Heat.1995.1080p.BluRay.x264-GROUP",
+                vec![
+                    file(10, "Heat/Sample/heat-sample.mkv", 50),
+                    file(11, "Heat/Extras/Making of.mkv", 9_000),
+                    file(12, "Heat/Heat.Theatrical.mkv", 7_000),
+                    file(13, "Heat/Heat.Directors.Cut.mkv", 8_000),
+                    file(14, "Heat/Heat.nfo", 1),
+                ],
+            )],
+            &Overrides::default(),
+        )
+        .unwrap();
+
+        assert_eq!(movies.len(), 1);
+        assert_eq!(movies[0].debrid_file_metadata.file_id, 13);
+    }
+
+    #[test]
+    fn it_keeps_titles_containing_a_marker() {
+        let release = "The.Interview.2014.1080p.BluRay.x264-GROUP";
+        let movies = parse_movies_from_torrents(
+            vec![
+                torrent(
+                    "1",
+                    release,
+                    vec![
+                        file(
+                            10,
+                            &format!("{}/Sample/the.interview.2014.sample.mkv", release),
+                            50,
+                        ),
+                        file(
+                            11,
+                            &format!("{}/The.Interview.2014.Trailer.mkv", release),
+                            90,
+                        ),
+                        file(12, &format!("{}/{}.mkv", release, release), 8_000),
+                    ],
+                ),
+                torrent(
+                    "2",
+                    "Interview.with.the.Vampire.1994.1080p.BluRay.x264-GROUP",
+                    vec![file(
+                        20,
+                        "Interview.with.the.Vampire.1994.1080p.BluRay.x264-GROUP.mkv",
+                        8_000,
+                    )],
+                ),
+            ],
+            &Overrides::default(),
+        )
+        .unwrap();
+
+        let mut files = movies
+            .iter()
+            .map(|movie| (movie.display_name(), movie.debrid_file_metadata.file_id))
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                ("Interview with the Vampire (1994)".to_string(), 20),
+                ("The Interview (2014)".to_string(), 12),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_skips_shows() {
+        let movies = parse_movies_from_torrents(
+            vec![torrent(
+                "1",
+                "Show.S01E01.1080p.WEB-DL",
+                vec![file(10, "Show.S01E01.1080p.WEB-DL.mkv", 1_000)],
+            )],
+            &Overrides::default(),
+        )
+        .unwrap();
+
+        assert!(movies.is_empty());
+    }
+
+    #[test]
+    fn it_skips_daily_episodes() {
+        let movies = parse_movies_from_torrents(
+            vec![torrent(
+                "1",
+                "The.Daily.Show.2024.01.15.1080p.WEB",
+                vec![file(10, "The.Daily.Show.2024.01.15.1080p.WEB.mkv", 1_000)],
+            )],
+            &Overrides::default(),
+        )
+        .unwrap();
+
+        assert!(movies.is_empty());
+    }
+}
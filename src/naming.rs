@@ -0,0 +1,457 @@
+use crate::dav_server::IMPORT_FOLDER;
+use crate::movies::Movie;
+use crate::shows::{ShowEpisode, ShowSeason};
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+const ROOT_TOKENS: [&str; 0] = [];
+const SHOW_TOKENS: [&str; 1] = ["title"];
+const SEASON_TOKENS: [&str; 1] = ["season"];
+const EPISODE_TOKENS: [&str; 7] = [
+    "title", "season", "episode", "air_date", "quality", "ext", "original",
+];
+const MOVIE_TOKENS: [&str; 6] = ["name", "title", "year", "quality", "ext", "original"];
+
+/// Names of the folders and files of the library, from the `[naming]` section of the config
+/// file. Templates are made of `{token}`s, numbers can be zero padded with `{token:00}`:
+///
+/// ```toml
+/// [naming]
+/// season_folder = "Season {season:00}"
+/// episode_file = "{title} - S{season:00}E{episode:00} - {quality}.{ext}"
+/// ```
+///
+/// The defaults keep the names found by the parsers.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct NamingConfig {
+    shows_root: String,
+    anime_root: String,
+    movies_root: String,
+    torrents_root: String,
+    show_folder: String,
+    season_folder: String,
+    specials_folder: String,
+    episode_file: String,
+    daily_episode_file: String,
+    movie_folder: String,
+    movie_file: String,
+}
+
+impl Default for NamingConfig {
+    fn default() -> Self {
+        NamingConfig {
+            shows_root: "shows".to_string(),
+            anime_root: "anime".to_string(),
+            movies_root: "movies".to_string(),
+            torrents_root: "torrents".to_string(),
+            show_folder: "{title}".to_string(),
+            season_folder: "Season {season}".to_string(),
+            specials_folder: "Specials".to_string(),
+            episode_file: "{original}".to_string(),
+            daily_episode_file: "{original}".to_string(),
+            movie_folder: "{name}".to_string(),
+            movie_file: "{original}".to_string(),
+        }
+    }
+}
+
+/// Validated naming templates, see [`NamingConfig`].
+#[derive(Debug, Clone)]
+pub struct Naming {
+    pub shows_root: String,
+    pub anime_root: String,
+    pub movies_root: String,
+    pub torrents_root: String,
+    show_folder: Template,
+    season_folder: Template,
+    specials_folder: String,
+    episode_file: Template,
+    daily_episode_file: Template,
+    movie_folder: Template,
+    movie_file: Template,
+}
+
+impl<'de> Deserialize<'de> for Naming {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let config = NamingConfig::deserialize(deserializer)?;
+        Naming::try_from(config).map_err(|e| serde::de::Error::custom(format!("{:#}", e)))
+    }
+}
+
+impl Default for Naming {
+    fn default() -> Self {
+        Naming::try_from(NamingConfig::default()).unwrap()
+    }
+}
+
+impl TryFrom<NamingConfig> for Naming {
+    type Error = anyhow::Error;
+
+    fn try_from(config: NamingConfig) -> Result<Self, Self::Error> {
+        let parse = |name: &str, source: &str, tokens: &[&str]| {
+            Template::parse(source, tokens).with_context(|| format!("invalid `{}`", name))
+        };
+        // The template must tell apart the episodes, or seasons, sharing a folder
+        let parse_unique = |name: &str, source: &str, tokens: &[&str], unique: &[&str]| {
+            let template = parse(name, source, tokens)?;
+            if !unique.iter().any(|token| template.uses(token)) {
+                anyhow::bail!(
+                    "invalid `{}`: `{}` must contain one of {:?}",
+                    name,
+                    source,
+                    unique
+                );
+            }
+            Ok(template)
+        };
+        for (name, root) in [
+            ("shows_root", &config.shows_root),
+            ("anime_root", &config.anime_root),
+            ("movies_root", &config.movies_root),
+            ("torrents_root", &config.torrents_root),
+            ("specials_folder", &config.specials_folder),
+        ] {
+            parse(name, root, &ROOT_TOKENS)?;
+        }
+        let roots = [
+            &config.shows_root,
+            &config.anime_root,
+            &config.movies_root,
+            &config.torrents_root,
+        ];
+        if roots
+            .iter()
+            .enumerate()
+            .any(|(index, root)| roots[..index].contains(root))
+        {
+            anyhow::bail!("the library roots must be different");
+        }
+        // Reserved for the import folder, which is served next to the library roots
+        let reserved = IMPORT_FOLDER.trim_start_matches('/');
+        if roots.iter().any(|root| root.as_str() == reserved) {
+            anyhow::bail!("`{}` can't be a library root", reserved);
+        }
+
+        Ok(Naming {
+            show_folder: parse("show_folder", &config.show_folder, &SHOW_TOKENS)?,
+            season_folder: parse_unique(
+                "season_folder",
+                &config.season_folder,
+                &SEASON_TOKENS,
+                &["season"],
+            )?,
+            episode_file: parse_unique(
+                "episode_file",
+                &config.episode_file,
+                &EPISODE_TOKENS,
+                &["episode", "original"],
+            )?,
+            daily_episode_file: parse_unique(
+                "daily_episode_file",
+                &config.daily_episode_file,
+                &EPISODE_TOKENS,
+                &["air_date", "original"],
+            )?,
+            movie_folder: parse("movie_folder", &config.movie_folder, &MOVIE_TOKENS)?,
+            movie_file: parse("movie_file", &config.movie_file, &MOVIE_TOKENS)?,
+            shows_root: config.shows_root,
+            anime_root: config.anime_root,
+            movies_root: config.movies_root,
+            torrents_root: config.torrents_root,
+            specials_folder: config.specials_folder,
+        })
+    }
+}
+
+impl Naming {
+    /// The folders at the root of the filesystem.
+    pub fn roots(&self) -> [&str; 4] {
+        [
+            &self.shows_root,
+            &self.anime_root,
+            &self.movies_root,
+            &self.torrents_root,
+        ]
+    }
+
+    pub fn show_folder(&self, title: &str) -> String {
+        self.show_folder
+            .render(&HashMap::from([("title", Value::Text(title.to_string()))]))
+    }
+
+    pub fn season_folder(&self, season: &ShowSeason) -> String {
+        match season.number {
+            0 => self.specials_folder.clone(),
+            number => self
+                .season_folder
+                .render(&HashMap::from([("season", Value::Number(number))])),
+        }
+    }
+
+    /// Name of the file of an episode, `quality` being its version label.
+    pub fn episode_file(
+        &self,
+        title: &str,
+        season: i32,
+        episode: &ShowEpisode,
+        quality: &str,
+    ) -> String {
+        let (first, last) = (*episode.numbers.start(), *episode.numbers.end());
+        let values = HashMap::from([
+            ("title", Value::Text(title.to_string())),
+            ("season", Value::Number(season)),
+            ("episode", Value::Range(first, last)),
+            (
+                "air_date",
+                Value::Text(
+                    episode
+                        .air_date
+                        .map(|air_date| air_date.to_string())
+                        .unwrap_or_default(),
+                ),
+            ),
+            ("quality", Value::Text(quality.to_string())),
+            (
+                "ext",
+                Value::Text(extension(&episode.file_name).to_string()),
+            ),
+            ("original", Value::Text(episode.file_name.clone())),
+        ]);
+        self.episode_template(episode).render(&values)
+    }
+
+    /// Whether the file of an episode is named after its version label.
+    pub fn episode_file_has_quality(&self, episode: &ShowEpisode) -> bool {
+        self.episode_template(episode).uses("quality")
+    }
+
+    fn episode_template(&self, episode: &ShowEpisode) -> &Template {
+        match episode.air_date {
+            Some(_) => &self.daily_episode_file,
+            None => &self.episode_file,
+        }
+    }
+
+    pub fn movie_folder(&self, movie: &Movie) -> String {
+        self.movie_folder.render(&movie_values(movie, ""))
+    }
+
+    /// Name of the file of a movie, `quality` being its version label.
+    pub fn movie_file(&self, movie: &Movie, quality: &str) -> String {
+        self.movie_file.render(&movie_values(movie, quality))
+    }
+
+    /// Whether movie files are named after their version label.
+    pub fn movie_file_has_quality(&self) -> bool {
+        self.movie_file.uses("quality")
+    }
+}
+
+fn movie_values(movie: &Movie, quality: &str) -> HashMap<&'static str, Value> {
+    HashMap::from([
+        ("name", Value::Text(movie.display_name())),
+        ("title", Value::Text(movie.title.clone())),
+        (
+            "year",
+            Value::Text(movie.year.map(|year| year.to_string()).unwrap_or_default()),
+        ),
+        ("quality", Value::Text(quality.to_string())),
+        ("ext", Value::Text(movie.extension.clone())),
+        ("original", Value::Text(movie.file_name())),
+    ])
+}
+
+fn extension(file_name: &str) -> &str {
+    file_name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension)
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    /// A token, numbers are padded with zeros to `width` digits.
+    Token {
+        name: String,
+        width: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Template {
+    segments: Vec<Segment>,
+}
+
+enum Value {
+    Text(String),
+    Number(i32),
+    /// Episodes of multi-episode files, rendered as `01-e02`.
+    Range(i32, i32),
+}
+
+impl Template {
+    fn parse(source: &str, tokens: &[&str]) -> anyhow::Result<Template> {
+        if source.trim().is_empty() {
+            anyhow::bail!("the template is empty");
+        }
+        if source.contains('/') {
+            anyhow::bail!("`{}` cannot contain a `/`", source);
+        }
+        if matches!(source.trim(), "." | "..") {
+            anyhow::bail!("`{}` is not a valid name", source);
+        }
+
+        let mut segments = vec![];
+        let mut rest = source;
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                anyhow::bail!("unexpected `}}` in `{}`", source);
+            }
+            let end = rest[start..]
+                .find('}')
+                .with_context(|| format!("unclosed `{{` in `{}`", source))?
+                + start;
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+
+            let token = &rest[start + 1..end];
+            let (name, format) = token.split_once(':').unwrap_or((token, ""));
+            if !tokens.contains(&name) {
+                anyhow::bail!(
+                    "unknown token `{{{}}}` in `{}`, expected one of {:?}",
+                    name,
+                    source,
+                    tokens
+                );
+            }
+            if !format.chars().all(|c| c == '0') {
+                anyhow::bail!("invalid format `{}` of token `{{{}}}`", format, name);
+            }
+            segments.push(Segment::Token {
+                name: name.to_string(),
+                width: format.len(),
+            });
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+        Ok(Template { segments })
+    }
+
+    fn uses(&self, token: &str) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| matches!(segment, Segment::Token { name, .. } if name == token))
+    }
+
+    fn render(&self, values: &HashMap<&str, Value>) -> String {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => rendered.push_str(literal),
+                Segment::Token { name, width } => match values.get(name.as_str()) {
+                    Some(Value::Text(text)) => rendered.push_str(&text.replace('/', "-")),
+                    Some(Value::Number(number)) => {
+                        rendered.push_str(&format!("{:0width$}", number, width = width))
+                    }
+                    Some(Value::Range(first, last)) if first == last => {
+                        rendered.push_str(&format!("{:0width$}", first, width = width))
+                    }
+                    Some(Value::Range(first, last)) => rendered.push_str(&format!(
+                        "{:0width$}-e{:0width$}",
+                        first,
+                        last,
+                        width = width
+                    )),
+                    None => {}
+                },
+            }
+        }
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn episode(numbers: std::ops::RangeInclusive<i32>) -> ShowEpisode {
+        ShowEpisode {
+            numbers,
+            air_date: None,
+            debrid_file_metadata: Default::default(),
+            size: 0,
+            quality: Default::default(),
+            file_name: "Show.S01E01.1080p.mkv".to_string(),
+        }
+    }
+
+    #[test]
+    fn it_renders_templates() {
+        let naming = Config::parse(
+            r#"
+            [naming]
+            shows_root = "TV"
+            season_folder = "Season {season:00}"
+            episode_file = "{title} - S{season:00}E{episode:00} - {quality}.{ext}"
+            "#,
+        )
+        .unwrap()
+        .naming;
+
+        assert_eq!(naming.shows_root, "TV");
+        let season = ShowSeason {
+            number: 3,
+            episodes: vec![],
+        };
+        assert_eq!(naming.season_folder(&season), "Season 03");
+        assert_eq!(
+            naming.episode_file("Show", 3, &episode(1..=1), "1080p"),
+            "Show - S03E01 - 1080p.mkv"
+        );
+        assert_eq!(
+            naming.episode_file("Show", 3, &episode(1..=2), "1080p"),
+            "Show - S03E01-e02 - 1080p.mkv"
+        );
+        assert!(naming.episode_file_has_quality(&episode(1..=1)));
+
+        let defaults = Naming::default();
+        assert_eq!(
+            defaults.episode_file("Show", 1, &episode(1..=1), "1080p"),
+            "Show.S01E01.1080p.mkv"
+        );
+        assert!(!defaults.episode_file_has_quality(&episode(1..=1)));
+    }
+
+    #[test]
+    fn it_rejects_invalid_templates() {
+        for naming in [
+            "season_folder = \"Season {number}\"",
+            "season_folder = \"Season {season\"",
+            "season_folder = \"Season }\"",
+            "season_folder = \"Season {season:xx}\"",
+            "show_folder = \"Shows/{title}\"",
+            "movie_file = \"\"",
+            "episode_file = \"{title}.{ext}\"",
+            "daily_episode_file = \"{title} - S{season:00}.{ext}\"",
+            "season_folder = \"Season\"",
+            "shows_root = \".\"",
+            "movies_root = \"..\"",
+            "specials_folder = \".\"",
+            "anime_root = \"shows\"",
+            "torrents_root = \"_import\"",
+        ] {
+            assert!(
+                Config::parse(&format!("[naming]\n{}", naming)).is_err(),
+                "{}",
+                naming
+            );
+        }
+    }
+}
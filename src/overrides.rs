@@ -0,0 +1,244 @@
+use crate::debrid::{DebridFile, DebridTorrent};
+use anyhow::Context;
+use regex::Regex;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+use tracing::info;
+
+/// Manual corrections of what the parsers find, read from a TOML file such as:
+///
+/// ```toml
+/// [[override]]
+/// file = '(?i)the\.office\.us'
+/// title = "The Office (US)"
+///
+/// [[override]]
+/// hash = "0123456789abcdef0123456789abcdef01234567"
+/// ignore = true
+/// ```
+///
+/// The first override matching a file applies.
+#[derive(Debug, Default)]
+pub struct Overrides {
+    overrides: Vec<Override>,
+}
+
+#[derive(Debug)]
+pub struct Override {
+    hash: Option<String>,
+    file: Option<Regex>,
+    pub title: Option<String>,
+    pub season: Option<i32>,
+    pub episode: Option<i32>,
+    pub ignore: bool,
+}
+
+#[derive(Deserialize)]
+struct OverridesConfig {
+    #[serde(default, rename = "override")]
+    overrides: Vec<OverrideConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OverrideConfig {
+    /// Info hash of the torrent.
+    hash: Option<String>,
+    /// Regex matched against the path of the file inside its torrent.
+    file: Option<String>,
+    title: Option<String>,
+    season: Option<i32>,
+    episode: Option<i32>,
+    #[serde(default)]
+    ignore: bool,
+}
+
+impl Overrides {
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let config: OverridesConfig = toml::from_str(content)?;
+        let overrides = config
+            .overrides
+            .into_iter()
+            .enumerate()
+            .map(|(index, config)| {
+                Override::try_from(config)
+                    .with_context(|| format!("Invalid override #{}", index + 1))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Overrides { overrides })
+    }
+
+    /// Finds the override applying to a file.
+    pub fn find(&self, torrent: &DebridTorrent, file: &DebridFile) -> Option<&Override> {
+        self.overrides
+            .iter()
+            .find(|file_override| file_override.matches(torrent, file))
+    }
+}
+
+impl Override {
+    fn matches(&self, torrent: &DebridTorrent, file: &DebridFile) -> bool {
+        let hash_matches = self
+            .hash
+            .as_ref()
+            .is_none_or(|hash| hash.eq_ignore_ascii_case(&torrent.hash));
+        let file_matches = self
+            .file
+            .as_ref()
+            .is_none_or(|file_regex| file_regex.is_match(&file.name));
+        hash_matches && file_matches
+    }
+
+    /// Whether the override turns the file into a specific episode.
+    pub fn is_episode(&self) -> bool {
+        self.season.is_some() && self.episode.is_some()
+    }
+}
+
+impl TryFrom<OverrideConfig> for Override {
+    type Error = anyhow::Error;
+
+    fn try_from(config: OverrideConfig) -> Result<Self, Self::Error> {
+        if config.hash.is_none() && config.file.is_none() {
+            anyhow::bail!("an override needs a `hash` or a `file` to match");
+        }
+        if !config.ignore
+            && config.title.is_none()
+            && config.season.is_none()
+            && config.episode.is_none()
+        {
+            anyhow::bail!("an override needs to set `ignore`, `title`, `season` or `episode`");
+        }
+        let file = config
+            .file
+            .map(|file| Regex::new(&file))
+            .transpose()
+            .context("invalid `file` regex")?;
+        Ok(Override {
+            hash: config.hash,
+            file,
+            title: config.title,
+            season: config.season,
+            episode: config.episode,
+            ignore: config.ignore,
+        })
+    }
+}
+
+/// Keeps the overrides in sync with the overrides file.
+pub struct OverridesFile {
+    path: Option<PathBuf>,
+    overrides: RwLock<Arc<Overrides>>,
+    modified: Mutex<Option<SystemTime>>,
+}
+
+impl OverridesFile {
+    /// Loads the overrides file, no overrides apply without one.
+    pub fn load(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let overrides_file = OverridesFile {
+            path,
+            overrides: RwLock::new(Arc::new(Overrides::default())),
+            modified: Mutex::new(None),
+        };
+        overrides_file.reload_if_changed()?;
+        Ok(overrides_file)
+    }
+
+    pub fn current(&self) -> Arc<Overrides> {
+        self.overrides.read().unwrap().clone()
+    }
+
+    /// Reloads the overrides when the file was modified since it was last read. On error the
+    /// previous overrides are kept.
+    ///
+    /// Returns whether the overrides changed.
+    pub fn reload_if_changed(&self) -> anyhow::Result<bool> {
+        let Some(path) = &self.path else {
+            return Ok(false);
+        };
+        let modified = modified_time(path)?;
+        if *self.modified.lock().unwrap() == Some(modified) {
+            return Ok(false);
+        }
+
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read overrides file {}", path.display()))?;
+        let overrides = Overrides::parse(&content)
+            .with_context(|| format!("Failed to parse overrides file {}", path.display()))?;
+        info!(
+            overrides = overrides.overrides.len(),
+            message = "Loaded overrides"
+        );
+        *self.overrides.write().unwrap() = Arc::new(overrides);
+        *self.modified.lock().unwrap() = Some(modified);
+        Ok(true)
+    }
+}
+
+fn modified_time(path: &Path) -> anyhow::Result<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("Failed to read overrides file {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn torrent(hash: &str) -> DebridTorrent {
+        DebridTorrent {
+            hash: hash.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn file(name: &str) -> DebridFile {
+        DebridFile {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_matches_hashes_and_files() {
+        let overrides = Overrides::parse(
+            r#"
+            [[override]]
+            hash = "ABCDEF"
+            ignore = true
+
+            [[override]]
+            file = "(?i)the\\.office\\.us"
+            title = "The Office (US)"
+            "#,
+        )
+        .unwrap();
+
+        assert!(
+            overrides
+                .find(&torrent("abcdef"), &file("anything.mkv"))
+                .unwrap()
+                .ignore
+        );
+        let file_override = overrides
+            .find(&torrent("123"), &file("The.Office.US.S01E01.mkv"))
+            .unwrap();
+        assert_eq!(file_override.title.as_deref(), Some("The Office (US)"));
+        assert!(!file_override.ignore);
+        assert!(
+            overrides
+                .find(&torrent("123"), &file("The.Office.UK.S01E01.mkv"))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn it_rejects_invalid_overrides() {
+        assert!(Overrides::parse("[[override]]\ntitle = \"Show\"").is_err());
+        assert!(Overrides::parse("[[override]]\nfile = \"show\"").is_err());
+        assert!(Overrides::parse("[[override]]\nfile = \"(\"\nignore = true").is_err());
+        assert!(Overrides::parse("[[override]]\nfile = \"show\"\nname = \"Show\"").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use crate::fake_file_system::{ChangeKind, PathChange};
+use crate::media_server::{
+    MediaServer, MediaServerConfig, MediaServerKind, default_debounce_seconds, default_retries,
+};
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Components of the deepest folder Plex is asked to scan, `/shows/<show>/<season>`.
+const MAX_SCAN_DEPTH: usize = 4;
+
+/// The `[plex]` section of the config file, a `[[media_servers]]` entry of kind `plex` written
+/// the way it was before other media servers were supported.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlexConfig {
+    pub url: String,
+    pub token: String,
+    pub mount_path: String,
+    pub sections: HashMap<String, u32>,
+    #[serde(default = "default_debounce_seconds")]
+    pub debounce_seconds: u64,
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+impl From<PlexConfig> for MediaServerConfig {
+    fn from(config: PlexConfig) -> Self {
+        MediaServerConfig {
+            kind: MediaServerKind::Plex,
+            url: config.url,
+            token: config.token,
+            mount_path: config.mount_path,
+            sections: config.sections,
+            debounce_seconds: config.debounce_seconds,
+            retries: config.retries,
+        }
+    }
+}
+
+/// Asks Plex to scan the folders that changed, in the library section of their root folder.
+pub struct Plex {
+    config: MediaServerConfig,
+    client: reqwest::Client,
+}
+
+impl Plex {
+    pub fn new(config: MediaServerConfig, client: reqwest::Client) -> Self {
+        Plex { config, client }
+    }
+
+    /// The section of the root folder of a folder, for the libraries Plex knows about.
+    fn section(&self, folder: &Path) -> Option<u32> {
+        let root = folder.strip_prefix("/").ok()?.components().next()?;
+        self.config
+            .sections
+            .get(root.as_os_str().to_str()?)
+            .copied()
+    }
+}
+
+impl MediaServer for Plex {
+    fn update_requests(&self, changes: &[PathChange]) -> Vec<reqwest::RequestBuilder> {
+        remove_nested(scan_folders(changes))
+            .into_iter()
+            .filter_map(|folder| {
+                let section = self.section(&folder)?;
+                let path = self.config.mounted_path(&folder);
+                info!(section, path, message = "Asking Plex to scan");
+                let url = format!(
+                    "{}/library/sections/{}/refresh",
+                    self.config.url.trim_end_matches('/'),
+                    section
+                );
+                Some(
+                    self.client
+                        .request(reqwest::Method::GET, url)
+                        .header("X-Plex-Token", &self.config.token)
+                        .query(&[("path", path)]),
+                )
+            })
+            .collect()
+    }
+}
+
+/// The folder Plex has to scan for each change: the added folder itself, or the folder holding
+/// the changed node. Never deeper than a season folder, nor above a library root.
+fn scan_folders(changes: &[PathChange]) -> BTreeSet<PathBuf> {
+    changes
+        .iter()
+        .map(|change| {
+            let folder = if change.folder && change.kind == ChangeKind::Added {
+                &change.path
+            } else {
+                change.path.parent().unwrap_or(&change.path)
+            };
+            let depth = folder.components().count().clamp(2, MAX_SCAN_DEPTH);
+            change.path.components().take(depth).collect()
+        })
+        .collect()
+}
+
+/// Scanning a folder covers the folders inside of it.
+fn remove_nested(folders: BTreeSet<PathBuf>) -> BTreeSet<PathBuf> {
+    folders
+        .iter()
+        .filter(|folder| {
+            !folder
+                .ancestors()
+                .skip(1)
+                .any(|ancestor| folders.contains(ancestor))
+        })
+        .cloned()
+        .collect()
+}
@@ -0,0 +1,742 @@
+use crate::TorrentFolders;
+use crate::content_database::ContentDatabase;
+use crate::debrid::{DebridAccounts, DebridProvider, NewTorrent};
+use anyhow::Context;
+use arc_swap::ArcSwap;
+use axum::extract::{FromRequest, Multipart, Query, Request, State};
+use axum::http::header::{CONTENT_TYPE, COOKIE, SET_COOKIE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Form, Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tracing::{error, info, warn};
+
+/// qBittorrent version Sonarr and Radarr are told about, recent enough for them to read the
+/// `content_path` of the torrents.
+const VERSION: &str = "v4.6.7";
+const WEBAPI_VERSION: &str = "2.9.3";
+
+/// The `[download_client]` section of the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DownloadClientConfig {
+    /// Credentials Sonarr and Radarr log in with. The API adds torrents to a debrid account and
+    /// downloads the `.torrent` URLs it is given, so it is never open to anyone.
+    pub username: String,
+    pub password: String,
+    /// Account the torrents are added to, the first one by default.
+    pub account: Option<String>,
+    /// Where the WebDAV server is mounted on Sonarr and Radarr, e.g. `/mnt/javelot`.
+    pub mount_path: String,
+}
+
+impl DownloadClientConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.username.is_empty() || self.password.is_empty() {
+            anyhow::bail!("username and password are required");
+        }
+        Ok(())
+    }
+
+    /// Path of a node of the filesystem on Sonarr and Radarr.
+    fn mounted_path(&self, path: &Path) -> String {
+        format!(
+            "{}/{}",
+            self.mount_path.trim_end_matches('/'),
+            path.strip_prefix("/").unwrap_or(path).display()
+        )
+    }
+}
+
+/// Serves the subset of the qBittorrent Web API used by Sonarr and Radarr, so they can send their
+/// grabs to a debrid account and import them from the torrents folder of the WebDAV mount.
+///
+/// Categories are kept in the content database. Deleting a torrent only forgets its category, the
+/// debrid account keeps it.
+pub struct QBittorrent {
+    config: DownloadClientConfig,
+    torrents_root: String,
+    debrid_accounts: Arc<DebridAccounts>,
+    content_database: Arc<ContentDatabase>,
+    /// Folder of each torrent in the published filesystem.
+    torrent_folders: Arc<ArcSwap<TorrentFolders>>,
+    /// Pulled when a finished torrent is not in the filesystem yet.
+    refresh_trigger: Arc<Notify>,
+    /// Hashes of the finished torrents a refresh was pulled for, each one only pulls one.
+    refresh_requested: Mutex<HashSet<String>>,
+    client: reqwest::Client,
+    /// Session ids handed out by the logins.
+    sessions: Mutex<HashSet<String>>,
+}
+
+impl QBittorrent {
+    pub fn new(
+        config: DownloadClientConfig,
+        torrents_root: String,
+        debrid_accounts: Arc<DebridAccounts>,
+        content_database: Arc<ContentDatabase>,
+        torrent_folders: Arc<ArcSwap<TorrentFolders>>,
+        refresh_trigger: Arc<Notify>,
+    ) -> anyhow::Result<Self> {
+        if let Some(account) = &config.account
+            && debrid_accounts.get(account).is_none()
+        {
+            anyhow::bail!("Unknown account {} in [download_client]", account);
+        }
+        Ok(QBittorrent {
+            config,
+            torrents_root,
+            debrid_accounts,
+            content_database,
+            torrent_folders,
+            refresh_trigger,
+            refresh_requested: Mutex::new(HashSet::new()),
+            client: reqwest::Client::new(),
+            sessions: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// Routes of the API, every one of them but the login requiring a session.
+    pub fn router<S: Clone + Send + Sync + 'static>(self) -> Router<S> {
+        let qbittorrent = Arc::new(self);
+        Router::new()
+            .route("/api/v2/app/version", get(|| async { VERSION }))
+            .route(
+                "/api/v2/app/webapiVersion",
+                get(|| async { WEBAPI_VERSION }),
+            )
+            .route("/api/v2/app/preferences", get(preferences))
+            .route("/api/v2/torrents/info", get(torrents_info))
+            .route("/api/v2/torrents/add", post(add_torrents))
+            .route("/api/v2/torrents/delete", post(delete_torrents))
+            .route("/api/v2/torrents/categories", get(categories))
+            .route("/api/v2/torrents/createCategory", post(create_category))
+            .route_layer(middleware::from_fn_with_state(
+                qbittorrent.clone(),
+                require_session,
+            ))
+            .route("/api/v2/auth/login", post(login))
+            .with_state(qbittorrent)
+    }
+
+    /// Where the torrents are, as seen by Sonarr and Radarr.
+    fn save_path(&self) -> String {
+        self.config
+            .mounted_path(&Path::new("/").join(&self.torrents_root))
+    }
+
+    fn account(&self) -> Option<&dyn DebridProvider> {
+        match &self.config.account {
+            Some(account) => self.debrid_accounts.get(account),
+            None => self
+                .debrid_accounts
+                .default_account()
+                .map(|account| account.provider.as_ref()),
+        }
+    }
+
+    /// Turns a URL of the add form into a torrent, downloading `.torrent` files.
+    async fn new_torrent(&self, url: &str) -> anyhow::Result<NewTorrent> {
+        if url.starts_with("magnet:") {
+            return Ok(NewTorrent::Magnet(url.to_string()));
+        }
+        let resp = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .context("Failed to send request")?;
+        if !resp.status().is_success() {
+            anyhow::bail!("Request failed: {}", resp.status());
+        }
+        let name = resp
+            .url()
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .unwrap_or_default()
+            .to_string();
+        let content = resp.bytes().await.context("Failed to read torrent")?;
+        Ok(NewTorrent::File {
+            name,
+            content: content.to_vec(),
+        })
+    }
+}
+
+async fn require_session(
+    State(qbittorrent): State<Arc<QBittorrent>>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Response {
+    let has_session = {
+        let sessions = qbittorrent.sessions.lock().unwrap();
+        headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|cookies| cookies.to_str().ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .filter_map(|cookie| cookie.trim().strip_prefix("SID="))
+            .any(|session| sessions.contains(session))
+    };
+    if !has_session {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    next.run(request).await
+}
+
+#[derive(Deserialize)]
+struct Login {
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    password: String,
+}
+
+async fn login(State(qbittorrent): State<Arc<QBittorrent>>, Form(login): Form<Login>) -> Response {
+    let config = &qbittorrent.config;
+    if config.username != login.username || config.password != login.password {
+        warn!(
+            username = login.username,
+            message = "Download client login failed"
+        );
+        return "Fails.".into_response();
+    }
+
+    let session = format!("{:032x}", rand::random::<u128>());
+    qbittorrent.sessions.lock().unwrap().insert(session.clone());
+    (
+        [(SET_COOKIE, format!("SID={}; HttpOnly; Path=/", session))],
+        "Ok.",
+    )
+        .into_response()
+}
+
+/// Seeding limits are disabled, debrid services don't seed on behalf of their users.
+async fn preferences(State(qbittorrent): State<Arc<QBittorrent>>) -> Json<Value> {
+    Json(json!({
+        "save_path": qbittorrent.save_path(),
+        "max_ratio_enabled": false,
+        "max_ratio": -1,
+        "max_seeding_time_enabled": false,
+        "max_seeding_time": -1,
+        "max_inactive_seeding_time_enabled": false,
+        "max_inactive_seeding_time": -1,
+        "queueing_enabled": false,
+        "dht": true,
+    }))
+}
+
+#[derive(Deserialize)]
+struct InfoQuery {
+    category: Option<String>,
+    /// `|` separated.
+    hashes: Option<String>,
+}
+
+/// A torrent, as listed by qBittorrent.
+#[derive(Debug, Serialize)]
+struct TorrentInfo {
+    hash: String,
+    name: String,
+    size: i64,
+    progress: f64,
+    /// Seconds, 8640000 standing for unknown.
+    eta: i64,
+    state: &'static str,
+    category: String,
+    save_path: String,
+    content_path: String,
+    added_on: i64,
+    ratio: f64,
+    /// -2 stands for the global limits.
+    ratio_limit: i64,
+    seeding_time_limit: i64,
+    inactive_seeding_time_limit: i64,
+}
+
+/// Lists the torrents of every account. Finished torrents are only reported as such once they
+/// are in the filesystem, so Sonarr and Radarr find their files when they import them. The first
+/// time a finished download is missing from it, a refresh is pulled.
+async fn torrents_info(
+    State(qbittorrent): State<Arc<QBittorrent>>,
+    Query(query): Query<InfoQuery>,
+) -> Result<Json<Vec<TorrentInfo>>, StatusCode> {
+    let downloads = qbittorrent
+        .content_database
+        .downloads()
+        .map_err(internal_error)?;
+    let torrents = qbittorrent
+        .debrid_accounts
+        .list_downloads()
+        .await
+        .map_err(internal_error)?;
+    let hashes = query.hashes.as_ref().filter(|hashes| *hashes != "all");
+
+    let torrent_folders = qbittorrent.torrent_folders.load();
+    let save_path = qbittorrent.save_path();
+
+    let mut refresh_requested = qbittorrent.refresh_requested.lock().unwrap();
+    let mut waiting_for_refresh = false;
+    let mut infos = vec![];
+    for torrent in &torrents {
+        let hash = torrent.hash.to_lowercase();
+        let download = downloads.get(&hash);
+        let category = download.map_or("", |download| download.category.as_str());
+        if query
+            .category
+            .as_ref()
+            .is_some_and(|expected| expected != category)
+            || hashes.is_some_and(|hashes| !hashes.split('|').any(|expected| expected == hash))
+        {
+            continue;
+        }
+
+        let name = torrent.name.clone().unwrap_or_else(|| torrent.id.clone());
+        let path = torrent_folders.get(&(torrent.account.clone(), torrent.id.clone()));
+        let state = match (torrent.download_finished, &path) {
+            (true, Some(_)) => {
+                refresh_requested.remove(&hash);
+                "pausedUP"
+            }
+            (true, None) => {
+                waiting_for_refresh |= download.is_some() && refresh_requested.insert(hash.clone());
+                "checkingUP"
+            }
+            (false, _) => "downloading",
+        };
+        let content_path = match &path {
+            Some(path) => qbittorrent.config.mounted_path(path),
+            None => format!("{}/{}", save_path, name),
+        };
+        infos.push(TorrentInfo {
+            hash,
+            name,
+            size: torrent.size,
+            progress: torrent.progress,
+            eta: if torrent.download_finished {
+                0
+            } else {
+                8640000
+            },
+            state,
+            category: category.to_string(),
+            save_path: save_path.clone(),
+            content_path,
+            added_on: download.map_or(0, |download| download.added_at),
+            ratio: 0.0,
+            ratio_limit: -2,
+            seeding_time_limit: -2,
+            inactive_seeding_time_limit: -2,
+        });
+    }
+    drop(refresh_requested);
+
+    if waiting_for_refresh {
+        info!("Refreshing filesystem for finished downloads");
+        qbittorrent.refresh_trigger.notify_one();
+    }
+    Ok(Json(infos))
+}
+
+/// The fields of the add form Javelot cares about.
+#[derive(Debug, Default)]
+struct AddForm {
+    /// Magnet links or URLs of `.torrent` files.
+    urls: Vec<String>,
+    torrents: Vec<NewTorrent>,
+    category: String,
+}
+
+impl AddForm {
+    /// Reads the form, sent as multipart when it holds `.torrent` files.
+    async fn read(request: Request) -> anyhow::Result<AddForm> {
+        let is_multipart = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with("multipart/form-data"));
+        let mut form = AddForm::default();
+        if !is_multipart {
+            let Form(fields) = Form::<HashMap<String, String>>::from_request(request, &()).await?;
+            form.add_urls(fields.get("urls").map_or("", String::as_str));
+            form.category = fields.get("category").cloned().unwrap_or_default();
+            return Ok(form);
+        }
+
+        let mut multipart = Multipart::from_request(request, &()).await?;
+        while let Some(field) = multipart.next_field().await? {
+            match field.name() {
+                Some("urls") => form.add_urls(&field.text().await?),
+                Some("category") => form.category = field.text().await?,
+                Some("torrents") => {
+                    let name = field.file_name().unwrap_or_default().to_string();
+                    let content = field.bytes().await?.to_vec();
+                    form.torrents.push(NewTorrent::File { name, content });
+                }
+                _ => {}
+            }
+        }
+        Ok(form)
+    }
+
+    /// URLs are separated by new lines.
+    fn add_urls(&mut self, urls: &str) {
+        self.urls.extend(
+            urls.lines()
+                .map(str::trim)
+                .filter(|url| !url.is_empty())
+                .map(String::from),
+        );
+    }
+}
+
+/// Adds the torrents to the debrid account, answering `Fails.` when one of them can't be.
+async fn add_torrents(State(qbittorrent): State<Arc<QBittorrent>>, request: Request) -> Response {
+    let form = match AddForm::read(request).await {
+        Ok(form) => form,
+        Err(e) => {
+            warn!("Invalid torrent add form: {:?}", e);
+            return (StatusCode::BAD_REQUEST, "Fails.").into_response();
+        }
+    };
+    let Some(account) = qbittorrent.account() else {
+        return internal_error(anyhow::anyhow!("No debrid account")).into_response();
+    };
+
+    let mut torrents = form.torrents;
+    let mut failed = false;
+    for url in &form.urls {
+        match qbittorrent.new_torrent(url).await {
+            Ok(torrent) => torrents.push(torrent),
+            Err(e) => {
+                error!("Failed to fetch torrent {}: {:?}", url, e);
+                failed = true;
+            }
+        }
+    }
+    for torrent in torrents {
+        let added = account.add_torrent(torrent).await.and_then(|hash| {
+            info!(hash, category = form.category, message = "Added torrent");
+            qbittorrent
+                .content_database
+                .add_download(&hash, &form.category)
+        });
+        if let Err(e) = added {
+            error!("Failed to add torrent: {:?}", e);
+            failed = true;
+        }
+    }
+
+    if failed { "Fails." } else { "Ok." }.into_response()
+}
+
+#[derive(Deserialize)]
+struct DeleteForm {
+    /// `|` separated, or `all`.
+    hashes: String,
+}
+
+async fn delete_torrents(
+    State(qbittorrent): State<Arc<QBittorrent>>,
+    Form(form): Form<DeleteForm>,
+) -> Result<(), StatusCode> {
+    let content_database = &qbittorrent.content_database;
+    let hashes = if form.hashes == "all" {
+        content_database
+            .downloads()
+            .map_err(internal_error)?
+            .into_keys()
+            .collect()
+    } else {
+        form.hashes.split('|').map(String::from).collect::<Vec<_>>()
+    };
+    for hash in hashes {
+        content_database
+            .remove_download(&hash)
+            .map_err(internal_error)?;
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Category {
+    name: String,
+    save_path: String,
+}
+
+async fn categories(
+    State(qbittorrent): State<Arc<QBittorrent>>,
+) -> Result<Json<BTreeMap<String, Category>>, StatusCode> {
+    let categories = qbittorrent
+        .content_database
+        .categories()
+        .map_err(internal_error)?;
+    Ok(Json(
+        categories
+            .into_iter()
+            .map(|name| {
+                let category = Category {
+                    name: name.clone(),
+                    save_path: qbittorrent.save_path(),
+                };
+                (name, category)
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize)]
+struct CreateCategoryForm {
+    category: String,
+}
+
+/// Creates a category, every category sharing the torrents folder.
+async fn create_category(
+    State(qbittorrent): State<Arc<QBittorrent>>,
+    Form(form): Form<CreateCategoryForm>,
+) -> Result<(), StatusCode> {
+    if form.category.trim().is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    qbittorrent
+        .content_database
+        .add_category(&form.category)
+        .map_err(internal_error)
+}
+
+fn internal_error(e: anyhow::Error) -> StatusCode {
+    error!("Download client request failed: {:?}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debrid::DebridAccount;
+    use crate::stub_server::{Recorder, serve};
+    use crate::torbox_client::{self, Torbox};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    /// Magnet links added to the stub.
+    type Magnets = Recorder<String>;
+
+    /// Serves the TorBox API with two finished torrents and a downloading one, recording the
+    /// magnet links of the torrents created.
+    async fn start_torbox_stub() -> (String, Magnets) {
+        let torrent = |id: i64, hash: &str, name: &str, progress: f64| torbox_client::Torrent {
+            id,
+            hash: hash.to_string(),
+            name: Some(name.to_string()),
+            size: 1000,
+            progress,
+            download_finished: progress == 1.0,
+            download_present: progress == 1.0,
+            ..Default::default()
+        };
+        let torrents = vec![
+            torrent(1, "aaa", "Show.S01", 1.0),
+            torrent(2, "bbb", "Heat.1995", 0.5),
+            torrent(3, "ccc", "Show.S02", 1.0),
+        ];
+
+        let magnets = Magnets::default();
+        let app = Router::new()
+            .route(
+                "/v1/api/torrents/mylist",
+                get(move || async move {
+                    Json(json!({"success": true, "error": null, "detail": "", "data": torrents}))
+                }),
+            )
+            .route(
+                "/v1/api/torrents/createtorrent",
+                post(
+                    |State(magnets): State<Magnets>, mut multipart: Multipart| async move {
+                        let field = multipart.next_field().await.unwrap().unwrap();
+                        assert_eq!(field.name(), Some("magnet"));
+                        let magnet = field.text().await.unwrap();
+                        let hash = magnet.rsplit(':').next().unwrap().to_lowercase();
+                        magnets.record(magnet);
+                        Json(json!({
+                            "success": true,
+                            "error": null,
+                            "detail": "Torrent added",
+                            "data": {"hash": hash, "torrent_id": 2, "auth_id": "auth"},
+                        }))
+                    },
+                ),
+            )
+            .with_state(magnets.clone());
+        (serve(app).await, magnets)
+    }
+
+    /// Serves the API in front of the stub, with `Show.S01` already in the filesystem. Returns
+    /// the trigger of the refreshes as well.
+    async fn start_download_client(torbox_url: String) -> (String, Arc<Notify>) {
+        // Folder names are told apart the way the refresh did, not recomputed
+        let torrent_folders = TorrentFolders::from([(
+            ("torbox-1".to_string(), "1".to_string()),
+            PathBuf::from("/torrents/Show.S01 [torbox-1 1]"),
+        )]);
+        let refresh_trigger = Arc::new(Notify::new());
+        let qbittorrent = QBittorrent::new(
+            DownloadClientConfig {
+                username: "admin".to_string(),
+                password: "secret".to_string(),
+                account: None,
+                mount_path: "/mnt/javelot".to_string(),
+            },
+            "torrents".to_string(),
+            Arc::new(DebridAccounts::new(vec![DebridAccount {
+                name: "torbox-1".to_string(),
+                provider: Arc::new(Torbox::with_base_url("key".to_string(), torbox_url)),
+            }])),
+            Arc::new(ContentDatabase::open(Path::new(":memory:")).unwrap()),
+            Arc::new(ArcSwap::from_pointee(torrent_folders)),
+            refresh_trigger.clone(),
+        )
+        .unwrap();
+
+        let app: Router = qbittorrent.router();
+        (format!("{}/api/v2", serve(app).await), refresh_trigger)
+    }
+
+    #[tokio::test]
+    async fn it_adds_torrents_and_reports_their_progress() {
+        let (torbox_url, magnets) = start_torbox_stub().await;
+        let (url, refresh_trigger) = start_download_client(torbox_url).await;
+        let client = reqwest::Client::new();
+
+        let info = client.get(format!("{}/torrents/info", url)).send().await;
+        assert_eq!(info.unwrap().status(), StatusCode::FORBIDDEN);
+        let login = |password: &'static str| {
+            client
+                .post(format!("{}/auth/login", url))
+                .form(&[("username", "admin"), ("password", password)])
+                .send()
+        };
+        assert_eq!(
+            login("wrong").await.unwrap().text().await.unwrap(),
+            "Fails."
+        );
+        let resp = login("secret").await.unwrap();
+        let cookie = resp.headers()[SET_COOKIE].to_str().unwrap();
+        let session = cookie.split(';').next().unwrap().to_string();
+        assert_eq!(resp.text().await.unwrap(), "Ok.");
+
+        let added = client
+            .post(format!("{}/torrents/add", url))
+            .header(COOKIE, &session)
+            .form(&[
+                (
+                    "urls",
+                    "magnet:?xt=urn:btih:AAA\nmagnet:?xt=urn:btih:BBB\nmagnet:?xt=urn:btih:CCC",
+                ),
+                ("category", "tv"),
+            ])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(added.text().await.unwrap(), "Ok.");
+        assert_eq!(
+            magnets.wait_for(3).await,
+            vec![
+                "magnet:?xt=urn:btih:AAA",
+                "magnet:?xt=urn:btih:BBB",
+                "magnet:?xt=urn:btih:CCC"
+            ]
+        );
+
+        let info = || async {
+            client
+                .get(format!("{}/torrents/info", url))
+                .query(&[("category", "tv")])
+                .header(COOKIE, &session)
+                .send()
+                .await
+                .unwrap()
+                .json::<Value>()
+                .await
+                .unwrap()
+        };
+        let pulled_refresh = || tokio::time::timeout(Duration::ZERO, refresh_trigger.notified());
+        let torrents = info()
+            .await
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|torrent| {
+                (
+                    torrent["hash"].clone(),
+                    torrent["state"].clone(),
+                    torrent["progress"].clone(),
+                    torrent["content_path"].clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            torrents,
+            vec![
+                (
+                    json!("aaa"),
+                    json!("pausedUP"),
+                    json!(1.0),
+                    json!("/mnt/javelot/torrents/Show.S01 [torbox-1 1]")
+                ),
+                (
+                    json!("bbb"),
+                    json!("downloading"),
+                    json!(0.5),
+                    json!("/mnt/javelot/torrents/Heat.1995")
+                ),
+                (
+                    json!("ccc"),
+                    json!("checkingUP"),
+                    json!(1.0),
+                    json!("/mnt/javelot/torrents/Show.S02")
+                ),
+            ]
+        );
+        // The finished torrent missing from the filesystem only pulls one refresh
+        assert!(pulled_refresh().await.is_ok());
+        info().await;
+        assert!(pulled_refresh().await.is_err());
+
+        let categories = client
+            .get(format!("{}/torrents/categories", url))
+            .header(COOKIE, &session)
+            .send()
+            .await
+            .unwrap()
+            .json::<Value>()
+            .await
+            .unwrap();
+        assert_eq!(
+            categories,
+            json!({"tv": {"name": "tv", "savePath": "/mnt/javelot/torrents"}})
+        );
+    }
+
+    #[test]
+    fn it_requires_credentials() {
+        let config = |content: &str| {
+            toml::from_str::<DownloadClientConfig>(content)
+                .map_err(anyhow::Error::from)
+                .and_then(|config| config.validate())
+        };
+        assert!(config("mount_path = \"/mnt\"").is_err());
+        assert!(config("username = \"admin\"\npassword = \"\"\nmount_path = \"/mnt\"").is_err());
+        assert!(
+            config("username = \"admin\"\npassword = \"secret\"\nmount_path = \"/mnt\"").is_ok()
+        );
+    }
+}
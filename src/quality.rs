@@ -0,0 +1,533 @@
+use crate::debrid::DebridFileMetadata;
+use crate::movies::Movie;
+use crate::shows::{Show, ShowEpisode};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::LazyLock;
+
+static RESOLUTION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)\b(2160p|4k|uhd|1080p|1080i|720p|576p|480p)\b").unwrap());
+static CODEC: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)\b([xh]\.?26[45]|hevc|avc|av1|xvid)\b").unwrap());
+static HDR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)\b(hdr|hdr10|hdr10\+|dv|dovi|dolby[ .]vision)\b").unwrap());
+static SOURCE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(remux|blu-?ray|bdrip|brrip|web-?dl|web-?rip|web|hdtv|dvdrip)\b").unwrap()
+});
+/// Release group at the end of a name, e.g. `Show.S01E01.1080p.WEB-DL-GROUP`.
+static GROUP: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"-(?P<group>[A-Za-z0-9]+)$").unwrap());
+
+const CODECS: [&str; 4] = ["hevc", "avc", "av1", "xvid"];
+const SOURCES: [&str; 6] = ["remux", "bluray", "web-dl", "webrip", "hdtv", "dvd"];
+
+/// Quality attributes of a release, parsed from its name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quality {
+    /// e.g. `2160p`, `1080p`.
+    pub resolution: Option<String>,
+    /// One of [`CODECS`].
+    pub codec: Option<String>,
+    pub hdr: bool,
+    /// One of [`SOURCES`].
+    pub source: Option<String>,
+    pub group: Option<String>,
+}
+
+impl Quality {
+    /// Parses the path of a file inside its torrent. The file name wins over the folders, which
+    /// usually carry the release name of the whole torrent.
+    pub fn parse(path: &str) -> Quality {
+        let mut quality = Quality::default();
+        for (index, part) in path.rsplit('/').enumerate() {
+            // Only the file has an extension, folders are named after the release
+            let stem = match part.rsplit_once('.') {
+                Some((stem, _)) if index == 0 => stem,
+                _ => part,
+            };
+            if quality.resolution.is_none() {
+                quality.resolution = RESOLUTION
+                    .find(part)
+                    .map(|resolution| normalize_resolution(resolution.as_str()));
+            }
+            if quality.codec.is_none() {
+                quality.codec = CODEC.find(part).map(|codec| {
+                    let codec = codec.as_str().to_lowercase().replace('.', "");
+                    match codec.as_str() {
+                        "x265" | "h265" => "hevc".to_string(),
+                        "x264" | "h264" => "avc".to_string(),
+                        _ => codec,
+                    }
+                });
+            }
+            quality.hdr |= HDR.is_match(part);
+            if quality.source.is_none() {
+                quality.source = SOURCE.find(part).map(|source| {
+                    let source = source.as_str().to_lowercase().replace('-', "");
+                    match source.as_str() {
+                        "bluray" | "bdrip" | "brrip" => "bluray",
+                        "webdl" | "web" => "web-dl",
+                        "dvdrip" => "dvd",
+                        source => source,
+                    }
+                    .to_string()
+                });
+            }
+            if quality.group.is_none() {
+                quality.group = GROUP
+                    .captures(stem)
+                    .map(|captures| captures["group"].to_string());
+            }
+        }
+        quality
+    }
+
+    /// Short description such as `2160p HDR`.
+    fn label(&self) -> String {
+        let mut parts = vec![];
+        if let Some(resolution) = &self.resolution {
+            parts.push(resolution.clone());
+        }
+        if self.hdr {
+            parts.push("HDR".to_string());
+        }
+        if parts.is_empty() {
+            parts.push("Unknown".to_string());
+        }
+        parts.join(" ")
+    }
+
+    /// Longer description such as `1080p WEB-DL NTb`, telling apart releases of the same label.
+    fn long_label(&self) -> String {
+        let mut parts = vec![self.label()];
+        if let Some(source) = &self.source {
+            parts.push(source.to_uppercase());
+        }
+        if let Some(group) = &self.group {
+            parts.push(group.clone());
+        }
+        parts.join(" ")
+    }
+}
+
+/// Labels of the releases of the same media, named after Plex's versions such as `2160p HDR`.
+/// Labels don't depend on the order of the releases, but only grow to tell apart siblings: releases
+/// sharing a label all get their source and group, then releases still sharing one all get their
+/// account, torrent and file ids. A label is shortened again once its clashing sibling is gone.
+pub fn version_labels<'a>(
+    releases: impl IntoIterator<Item = (&'a Quality, &'a DebridFileMetadata)>,
+) -> Vec<String> {
+    let releases = releases.into_iter().collect::<Vec<_>>();
+    let labels = releases
+        .iter()
+        .map(|(quality, _)| quality.label())
+        .collect::<Vec<_>>();
+    let clashing = clashing_labels(&labels);
+    let labels = releases
+        .iter()
+        .zip(labels)
+        .map(|((quality, _), label)| {
+            if clashing.contains(&label) {
+                quality.long_label()
+            } else {
+                label
+            }
+        })
+        .collect::<Vec<_>>();
+    let clashing = clashing_labels(&labels);
+    releases
+        .iter()
+        .zip(labels)
+        .map(|((_, file), label)| {
+            if clashing.contains(&label) {
+                format!(
+                    "{} {} {}-{}",
+                    label, file.account, file.torrent_id, file.file_id
+                )
+            } else {
+                label
+            }
+        })
+        .collect()
+}
+
+/// Labels given to more than one release.
+fn clashing_labels(labels: &[String]) -> HashSet<String> {
+    let mut seen = HashSet::new();
+    labels
+        .iter()
+        .filter(|label| !seen.insert(*label))
+        .cloned()
+        .collect()
+}
+
+/// Names a resolution the way releases are compared, e.g. `4K` is `2160p`.
+fn normalize_resolution(resolution: &str) -> String {
+    match resolution.to_lowercase().as_str() {
+        "4k" | "uhd" => "2160p".to_string(),
+        "1080i" => "1080p".to_string(),
+        resolution => resolution.to_string(),
+    }
+}
+
+/// How to pick the release exposed when several provide the same episode, from the `[quality]`
+/// section of the config file:
+///
+/// ```toml
+/// [quality]
+/// resolutions = ["1080p", "2160p", "720p"]
+/// blocked_groups = ["YIFY"]
+/// expose_alternates = true
+/// ```
+///
+/// Releases are compared on their size limit, resolution, HDR, source, codec, preferred group
+/// and finally their size, in that order.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QualityPolicy {
+    /// Resolutions from the most to the least preferred, unlisted ones come last.
+    pub resolutions: Vec<String>,
+    /// Codecs from the most to the least preferred, unlisted ones come last.
+    pub codecs: Vec<String>,
+    /// Sources from the most to the least preferred, unlisted ones come last.
+    pub sources: Vec<String>,
+    pub prefer_hdr: bool,
+    /// Releases larger than this many megabytes are only picked when nothing else is available.
+    pub max_size_mb: Option<i64>,
+    /// Picks the smallest release instead of the largest when everything else is equal.
+    pub prefer_smaller: bool,
+    pub preferred_groups: Vec<String>,
+    /// Releases of these groups are never exposed.
+    pub blocked_groups: Vec<String>,
+    /// Exposes the releases that lost next to the winner.
+    pub expose_alternates: bool,
+    /// Exposes every release of an episode or movie in the same folder, named after its quality
+    /// so Plex offers them as versions, e.g. `Show - s01e01 - [2160p HDR].mkv`.
+    pub multi_version: bool,
+}
+
+impl Default for QualityPolicy {
+    fn default() -> Self {
+        QualityPolicy {
+            resolutions: ["2160p", "1080p", "720p", "576p", "480p"]
+                .map(String::from)
+                .to_vec(),
+            codecs: ["hevc", "avc"].map(String::from).to_vec(),
+            sources: SOURCES.map(String::from).to_vec(),
+            prefer_hdr: true,
+            max_size_mb: None,
+            prefer_smaller: false,
+            preferred_groups: vec![],
+            blocked_groups: vec![],
+            expose_alternates: false,
+            multi_version: false,
+        }
+    }
+}
+
+impl QualityPolicy {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for resolution in &self.resolutions {
+            if RESOLUTION
+                .find(resolution)
+                .is_none_or(|found| found.len() != resolution.len())
+            {
+                anyhow::bail!("unknown resolution {}", resolution);
+            }
+            // Releases are compared on their normalized resolution, an alias would never match
+            let normalized = normalize_resolution(resolution);
+            if !normalized.eq_ignore_ascii_case(resolution) {
+                anyhow::bail!("resolution {} is written {}", resolution, normalized);
+            }
+        }
+        for codec in &self.codecs {
+            if !CODECS.contains(&codec.to_lowercase().as_str()) {
+                anyhow::bail!("unknown codec {}, expected one of {:?}", codec, CODECS);
+            }
+        }
+        for source in &self.sources {
+            if !SOURCES.contains(&source.to_lowercase().as_str()) {
+                anyhow::bail!("unknown source {}, expected one of {:?}", source, SOURCES);
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps the best release of every episode, first in its season, followed by the other
+    /// releases when alternates are exposed. Releases of blocked groups are dropped.
+    pub fn select_releases(&self, shows: Vec<Show>) -> Vec<Show> {
+        let mut selected = vec![];
+        for mut show in shows {
+            for season in show.seasons.values_mut() {
+                let mut releases: BTreeMap<(i32, i32), Vec<ShowEpisode>> = BTreeMap::new();
+                for episode in season.episodes.drain(..) {
+                    if self.is_blocked(&episode.quality) {
+                        continue;
+                    }
+                    releases
+                        .entry((*episode.numbers.start(), *episode.numbers.end()))
+                        .or_default()
+                        .push(episode);
+                }
+
+                for mut episodes in releases.into_values() {
+                    episodes.sort_by_cached_key(|episode| {
+                        self.rank(
+                            &episode.quality,
+                            episode.size,
+                            &episode.debrid_file_metadata,
+                        )
+                    });
+                    if !self.keeps_alternates() {
+                        episodes.truncate(1);
+                    }
+                    season.episodes.extend(episodes);
+                }
+            }
+            show.seasons.retain(|_, season| !season.episodes.is_empty());
+            if !show.seasons.is_empty() {
+                selected.push(show);
+            }
+        }
+        selected
+    }
+
+    /// Keeps the best release of every movie, followed by the other releases when alternates
+    /// are exposed. Releases of the same movie are next to each other.
+    pub fn select_movies(&self, movies: Vec<Movie>) -> Vec<Movie> {
+        let mut releases: BTreeMap<String, Vec<Movie>> = BTreeMap::new();
+        for movie in movies {
+            if !self.is_blocked(&movie.quality) {
+                releases
+                    .entry(movie.display_name())
+                    .or_default()
+                    .push(movie);
+            }
+        }
+
+        let mut selected = vec![];
+        for mut movies in releases.into_values() {
+            movies.sort_by_cached_key(|movie| {
+                self.rank(&movie.quality, movie.size, &movie.debrid_file_metadata)
+            });
+            if !self.keeps_alternates() {
+                movies.truncate(1);
+            }
+            selected.extend(movies);
+        }
+        selected
+    }
+
+    /// Ranks releases that are not on the debrid accounts, e.g. search results, from the best.
+    /// Releases of blocked groups are dropped, and only the best one is kept unless alternates
+    /// are exposed.
+    pub fn select_candidates<T>(
+        &self,
+        candidates: Vec<T>,
+        quality_and_size: impl Fn(&T) -> (&Quality, i64),
+    ) -> Vec<T> {
+        let mut candidates = candidates
+            .into_iter()
+            .filter(|candidate| !self.is_blocked(quality_and_size(candidate).0))
+            .collect::<Vec<_>>();
+        // Stable, so equal releases keep the order of the source
+        candidates.sort_by_cached_key(|candidate| {
+            let (quality, size) = quality_and_size(candidate);
+            self.rank(quality, size, &DebridFileMetadata::default())
+        });
+        if !self.keeps_alternates() {
+            candidates.truncate(1);
+        }
+        candidates
+    }
+
+    fn keeps_alternates(&self) -> bool {
+        self.expose_alternates || self.multi_version
+    }
+
+    fn is_blocked(&self, quality: &Quality) -> bool {
+        quality.group.as_ref().is_some_and(|group| {
+            self.blocked_groups
+                .iter()
+                .any(|blocked| blocked.eq_ignore_ascii_case(group))
+        })
+    }
+
+    /// Sort key of a release, the best release comes first.
+    fn rank(
+        &self,
+        quality: &Quality,
+        size: i64,
+        debrid_file_metadata: &DebridFileMetadata,
+    ) -> impl Ord + use<> {
+        let too_large = self
+            .max_size_mb
+            .is_some_and(|max_size_mb| size > max_size_mb * 1024 * 1024);
+        let preferred_group = quality.group.as_ref().is_some_and(|group| {
+            self.preferred_groups
+                .iter()
+                .any(|preferred| preferred.eq_ignore_ascii_case(group))
+        });
+        let size = if self.prefer_smaller { size } else { -size };
+        (
+            too_large,
+            preference(&self.resolutions, quality.resolution.as_deref()),
+            self.prefer_hdr && !quality.hdr,
+            preference(&self.sources, quality.source.as_deref()),
+            preference(&self.codecs, quality.codec.as_deref()),
+            Reverse(preferred_group),
+            size,
+            // Keeps the choice stable across refreshes
+            debrid_file_metadata.clone(),
+        )
+    }
+}
+
+/// Position of a value in a preference list, unknown and unlisted values come last.
+fn preference(preferences: &[String], value: Option<&str>) -> usize {
+    value
+        .and_then(|value| {
+            preferences
+                .iter()
+                .position(|preference| preference.eq_ignore_ascii_case(value))
+        })
+        .unwrap_or(preferences.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shows::group_episodes;
+
+    fn release(name: &str, size: i64) -> (String, i32, ShowEpisode) {
+        (
+            "Show".to_string(),
+            2,
+            ShowEpisode {
+                numbers: 5..=5,
+                air_date: None,
+                debrid_file_metadata: DebridFileMetadata {
+                    torrent_id: name.to_string(),
+                    ..Default::default()
+                },
+                size,
+                quality: Quality::parse(name),
+                file_name: name.to_string(),
+            },
+        )
+    }
+
+    fn selected(policy: &QualityPolicy, releases: Vec<(String, i32, ShowEpisode)>) -> Vec<String> {
+        policy.select_releases(group_episodes(releases))[0].seasons[&2]
+            .episodes
+            .iter()
+            .map(|episode| episode.file_name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn it_parses_quality() {
+        assert_eq!(
+            Quality::parse("Show.S02.2160p.WEB-DL.DV.HDR.H.265-NTb/Show.S02E05.mkv"),
+            Quality {
+                resolution: Some("2160p".to_string()),
+                codec: Some("hevc".to_string()),
+                hdr: true,
+                source: Some("web-dl".to_string()),
+                group: Some("NTb".to_string()),
+            }
+        );
+        assert_eq!(
+            Quality::parse("Show.S02E05.720p.HDTV.x264-KILLERS.mkv").group,
+            Some("KILLERS".to_string())
+        );
+    }
+
+    #[test]
+    fn it_picks_the_best_release() {
+        let releases = vec![
+            release("Show.S02E05.720p.HDTV.x264-KILLERS.mkv", 500),
+            release("Show.S02E05.2160p.WEB-DL.HDR.x265-NTb.mkv", 8_000),
+            release("Show.S02E05.1080p.BluRay.x264-YIFY.mkv", 2_000),
+        ];
+
+        assert_eq!(
+            selected(&QualityPolicy::default(), releases.clone()),
+            vec!["Show.S02E05.2160p.WEB-DL.HDR.x265-NTb.mkv"]
+        );
+
+        let policy = QualityPolicy {
+            resolutions: vec!["1080p".to_string(), "720p".to_string()],
+            blocked_groups: vec!["yify".to_string()],
+            expose_alternates: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            selected(&policy, releases),
+            vec![
+                "Show.S02E05.720p.HDTV.x264-KILLERS.mkv",
+                "Show.S02E05.2160p.WEB-DL.HDR.x265-NTb.mkv"
+            ]
+        );
+    }
+
+    #[test]
+    fn it_labels_versions_uniquely() {
+        let releases = [
+            ("Show.S01E01.2160p.WEB-DL.HDR.x265-NTb.mkv", 1),
+            ("Show.S01E01.1080p.WEB-DL.x264-NTb.mkv", 2),
+            ("Show.S01E01.1080p.BluRay.x264-GROUP.mkv", 3),
+            ("Show.S01E01.1080p.BluRay.x264-GROUP.mp4", 4),
+            ("Show.S01E01.mkv", 5),
+        ]
+        .map(|(name, torrent_id)| {
+            (
+                Quality::parse(name),
+                DebridFileMetadata {
+                    account: "torbox-1".to_string(),
+                    torrent_id: torrent_id.to_string(),
+                    file_id: 7,
+                },
+            )
+        });
+        let labels = |releases: &[(Quality, DebridFileMetadata)]| {
+            version_labels(releases.iter().map(|(quality, file)| (quality, file)))
+        };
+
+        assert_eq!(
+            labels(&releases),
+            vec![
+                "2160p HDR",
+                "1080p WEB-DL NTb",
+                "1080p BLURAY GROUP torbox-1 3-7",
+                "1080p BLURAY GROUP torbox-1 4-7",
+                "Unknown"
+            ]
+        );
+        // Labels don't depend on the order of the releases
+        let mut reversed = labels(&releases);
+        reversed.reverse();
+        let mut releases = releases;
+        releases.reverse();
+        assert_eq!(labels(&releases), reversed);
+        // The label of a release depends on its siblings, it is short again without a clash
+        assert_eq!(labels(&releases[..2]), vec!["Unknown", "1080p"]);
+
+        // The same torrent and file ids on two merged accounts
+        let other_account = (
+            releases[1].0.clone(),
+            DebridFileMetadata {
+                account: "torbox-2".to_string(),
+                ..releases[1].1.clone()
+            },
+        );
+        assert_eq!(
+            labels(&[releases[1].clone(), other_account]),
+            vec![
+                "1080p BLURAY GROUP torbox-1 4-7",
+                "1080p BLURAY GROUP torbox-2 4-7"
+            ]
+        );
+    }
+}
@@ -0,0 +1,518 @@
+use crate::debrid::{DebridFile, DebridProvider, DebridTorrent, NewTorrent};
+use anyhow::Context;
+use async_trait::async_trait;
+use headers::HeaderValue;
+use moka::future::Cache;
+use reqwest::Response;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Number of torrents requested per page when listing torrents.
+const PAGE_SIZE: usize = 1000;
+/// How many times an added torrent is checked for its files to be selectable, magnets have to be
+/// converted first.
+const FILES_SELECTION_ATTEMPTS: u32 = 10;
+const FILES_SELECTION_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct RealDebrid {
+    api_key: String,
+    base_url: String,
+    client: reqwest::Client,
+    cache: Cache<String, String>,
+    /// Files of the downloaded torrents, by id and end date. A torrent does not change once
+    /// downloaded, so its info is only fetched again when it is downloaded again.
+    info_cache: Cache<(String, Option<String>), DebridTorrent>,
+    files_selection_delay: Duration,
+}
+
+impl RealDebrid {
+    pub fn new(api_key: String) -> Self {
+        RealDebrid::with_base_url(api_key, "https://api.real-debrid.com/rest/1.0".to_string())
+    }
+
+    pub fn with_base_url(api_key: String, base_url: String) -> Self {
+        RealDebrid {
+            api_key,
+            base_url,
+            client: reqwest::Client::new(),
+            cache: Cache::builder()
+                .time_to_idle(Duration::from_secs(60 * 60 * 3))
+                .build(),
+            info_cache: Cache::builder()
+                .time_to_idle(Duration::from_secs(60 * 60 * 24))
+                .build(),
+            files_selection_delay: FILES_SELECTION_DELAY,
+        }
+    }
+
+    async fn torrent_info(&self, torrent_id: &str) -> anyhow::Result<TorrentInfo> {
+        let url = format!("{}/torrents/info/{}", self.base_url, torrent_id);
+        let request = self
+            .client
+            .request(reqwest::Method::GET, url)
+            .bearer_auth(&self.api_key);
+        let resp = request.send().await.context("Failed to send request")?;
+        if !resp.status().is_success() {
+            anyhow::bail!("Request failed: {}", resp.status());
+        }
+        resp.json::<TorrentInfo>()
+            .await
+            .context("Failed to parse json")
+    }
+
+    /// Turns the hoster link of a file into a direct download link.
+    async fn unrestrict_link(&self, link: &str) -> anyhow::Result<String> {
+        let url = format!("{}/unrestrict/link", self.base_url);
+        let request = self
+            .client
+            .request(reqwest::Method::POST, url)
+            .form(&[("link", link)])
+            .bearer_auth(&self.api_key);
+        let resp = request.send().await.context("Failed to send request")?;
+        if !resp.status().is_success() {
+            anyhow::bail!("Request failed: {}", resp.status());
+        }
+        let json = resp
+            .json::<UnrestrictLinkResponse>()
+            .await
+            .context("Failed to parse json")?;
+        Ok(json.download)
+    }
+
+    /// Lists every torrent, without their files.
+    async fn list_all(&self) -> anyhow::Result<Vec<Torrent>> {
+        let mut torrents = vec![];
+        for page in 1.. {
+            let url = format!("{}/torrents", self.base_url);
+            let request = self
+                .client
+                .request(reqwest::Method::GET, url)
+                .query(&[("page", page.to_string()), ("limit", PAGE_SIZE.to_string())])
+                .bearer_auth(&self.api_key);
+            let resp = request.send().await.context("Failed to send request")?;
+            if !resp.status().is_success() {
+                anyhow::bail!("Request failed: {}", resp.status());
+            }
+            // An empty page is answered with a 204 and no body
+            if resp.status() == reqwest::StatusCode::NO_CONTENT {
+                break;
+            }
+            let json = resp
+                .json::<Vec<Torrent>>()
+                .await
+                .context("Failed to parse json")?;
+            let page_len = json.len();
+            torrents.extend(json);
+            if page_len < PAGE_SIZE {
+                break;
+            }
+        }
+        Ok(torrents)
+    }
+
+    /// Selects every file of an added torrent once Real-Debrid knows them, so it starts
+    /// downloading it. Returns the hash of the torrent.
+    ///
+    /// A magnet still being converted after a few attempts is left as is, its files can be
+    /// selected on Real-Debrid later on.
+    async fn select_files_when_ready(&self, torrent_id: &str) -> anyhow::Result<String> {
+        let mut attempts = 1;
+        loop {
+            let info = self.torrent_info(torrent_id).await?;
+            match info.status.as_str() {
+                "waiting_files_selection" => {
+                    self.select_all_files(torrent_id).await?;
+                    return Ok(info.hash.to_lowercase());
+                }
+                "magnet_conversion" if attempts < FILES_SELECTION_ATTEMPTS => {
+                    tokio::time::sleep(self.files_selection_delay).await;
+                    attempts += 1;
+                }
+                "magnet_conversion" => {
+                    warn!(
+                        torrent_id,
+                        "Magnet still not converted, files are not selected"
+                    );
+                    return Ok(info.hash.to_lowercase());
+                }
+                "magnet_error" | "error" | "virus" | "dead" => {
+                    anyhow::bail!("Torrent can't be downloaded: {}", info.status);
+                }
+                // Files of torrents already on the account are already selected
+                _ => return Ok(info.hash.to_lowercase()),
+            }
+        }
+    }
+
+    /// Selects every file of an added torrent, so Real-Debrid starts downloading it.
+    async fn select_all_files(&self, torrent_id: &str) -> anyhow::Result<()> {
+        let url = format!("{}/torrents/selectFiles/{}", self.base_url, torrent_id);
+        let request = self
+            .client
+            .request(reqwest::Method::POST, url)
+            .form(&[("files", "all")])
+            .bearer_auth(&self.api_key);
+        let resp = request.send().await.context("Failed to send request")?;
+        if !resp.status().is_success() {
+            anyhow::bail!("Request failed: {}", resp.status());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DebridProvider for RealDebrid {
+    async fn list_torrents(&self) -> anyhow::Result<Vec<DebridTorrent>> {
+        let torrents = self.list_all().await?;
+
+        // The listing does not contain files, they have to be fetched for each new torrent
+        let mut active_torrents = vec![];
+        for torrent in torrents
+            .into_iter()
+            .filter(|torrent| torrent.status == "downloaded")
+        {
+            let key = (torrent.id.clone(), torrent.ended.clone());
+            let active_torrent = self
+                .info_cache
+                .try_get_with(key, async {
+                    self.torrent_info(&torrent.id)
+                        .await
+                        .map(DebridTorrent::from)
+                })
+                .await
+                .map_err(|e| anyhow::anyhow!("{:#}", e))?;
+            active_torrents.push(active_torrent);
+        }
+        Ok(active_torrents)
+    }
+
+    async fn list_downloads(&self) -> anyhow::Result<Vec<DebridTorrent>> {
+        Ok(self
+            .list_all()
+            .await?
+            .into_iter()
+            .map(DebridTorrent::from)
+            .collect())
+    }
+
+    async fn add_torrent(&self, torrent: NewTorrent) -> anyhow::Result<String> {
+        let request = match torrent {
+            NewTorrent::Magnet(magnet) => self
+                .client
+                .request(
+                    reqwest::Method::POST,
+                    format!("{}/torrents/addMagnet", self.base_url),
+                )
+                .form(&[("magnet", magnet)]),
+            NewTorrent::File { content, .. } => self
+                .client
+                .request(
+                    reqwest::Method::PUT,
+                    format!("{}/torrents/addTorrent", self.base_url),
+                )
+                .body(content),
+        };
+        let resp = request
+            .bearer_auth(&self.api_key)
+            .send()
+            .await
+            .context("Failed to send request")?;
+        if !resp.status().is_success() {
+            anyhow::bail!("Request failed: {}", resp.status());
+        }
+        let json = resp
+            .json::<AddTorrentResponse>()
+            .await
+            .context("Failed to parse json")?;
+        self.select_files_when_ready(&json.id).await
+    }
+
+    async fn torrent_stream(
+        &self,
+        torrent_id: &str,
+        file_id: i64,
+        range_header: Option<HeaderValue>,
+    ) -> anyhow::Result<Response> {
+        let key = format!("torrent_id:{},file_id:{}", torrent_id, file_id);
+
+        let url = self
+            .cache
+            .try_get_with(key, async {
+                info!("File {} {} not present in cache", file_id, torrent_id);
+                let info = self.torrent_info(torrent_id).await?;
+                // Real-Debrid gives one link per selected file, in the order of the files
+                let link_index = info
+                    .files
+                    .iter()
+                    .filter(|file| file.selected == 1)
+                    .position(|file| file.id == file_id)
+                    .context("File is not part of the torrent")?;
+                let link = info
+                    .links
+                    .get(link_index)
+                    .context("File has no download link")?;
+                self.unrestrict_link(link).await
+            })
+            .await;
+
+        if let Ok(url) = url {
+            let mut request = self
+                .client
+                .request(reqwest::Method::GET, url)
+                .bearer_auth(&self.api_key);
+
+            if let Some(range) = range_header {
+                request = request.header("Range", range);
+            }
+
+            let resp = request.send().await.context("Failed to send request")?;
+            return Ok(resp);
+        }
+
+        anyhow::bail!("Failed to download torrent file");
+    }
+}
+
+impl From<TorrentInfo> for DebridTorrent {
+    fn from(info: TorrentInfo) -> Self {
+        let files = info
+            .files
+            .into_iter()
+            .filter(|file| file.selected == 1)
+            .map(|file| {
+                let path = file.path.trim_start_matches('/');
+                // The file of a single file torrent is named after the torrent itself
+                let name = if path == info.filename {
+                    path.to_string()
+                } else {
+                    format!("{}/{}", info.filename, path)
+                };
+                DebridFile {
+                    id: file.id,
+                    name,
+                    short_name: path.rsplit('/').next().unwrap_or(path).to_string(),
+                    size: file.bytes,
+                }
+            })
+            .collect();
+
+        DebridTorrent {
+            // Filled in by the account listing the torrent
+            account: String::new(),
+            id: info.id,
+            hash: info.hash,
+            name: Some(info.filename),
+            size: info.bytes,
+            updated_at: info.ended.clone().unwrap_or_else(|| info.added.clone()),
+            created_at: info.added,
+            progress: info.progress / 100.0,
+            download_finished: info.status == "downloaded",
+            files,
+        }
+    }
+}
+
+impl From<Torrent> for DebridTorrent {
+    fn from(torrent: Torrent) -> Self {
+        DebridTorrent {
+            // Filled in by the account listing the torrent
+            account: String::new(),
+            id: torrent.id,
+            hash: torrent.hash,
+            name: Some(torrent.filename),
+            size: torrent.bytes,
+            updated_at: torrent
+                .ended
+                .clone()
+                .unwrap_or_else(|| torrent.added.clone()),
+            created_at: torrent.added,
+            progress: torrent.progress / 100.0,
+            download_finished: torrent.status == "downloaded",
+            files: vec![],
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Torrent {
+    pub id: String,
+    pub filename: String,
+    pub hash: String,
+    pub bytes: i64,
+    pub host: String,
+    pub split: i64,
+    pub progress: f64,
+    pub status: String,
+    pub added: String,
+    pub links: Vec<String>,
+    pub ended: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TorrentInfo {
+    pub id: String,
+    pub filename: String,
+    #[serde(rename = "original_filename")]
+    pub original_filename: String,
+    pub hash: String,
+    pub bytes: i64,
+    #[serde(rename = "original_bytes")]
+    pub original_bytes: i64,
+    pub host: String,
+    pub split: i64,
+    pub progress: f64,
+    pub status: String,
+    pub added: String,
+    pub files: Vec<File>,
+    pub links: Vec<String>,
+    pub ended: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct File {
+    pub id: i64,
+    pub path: String,
+    pub bytes: i64,
+    pub selected: i64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct AddTorrentResponse {
+    pub id: String,
+    pub uri: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct UnrestrictLinkResponse {
+    pub id: String,
+    pub filename: String,
+    pub filesize: i64,
+    pub link: String,
+    pub download: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub_server::{Recorder, serve};
+    use axum::Router;
+    use axum::extract::State;
+    use axum::routing::{get, post};
+    use serde_json::json;
+
+    /// Serves the Real-Debrid API, converting added magnets on the second look at them. Records
+    /// the paths of the requests.
+    async fn start_stub_server() -> (String, Recorder<String>) {
+        let requests = Recorder::default();
+        let app = Router::new()
+            .route(
+                "/torrents/addMagnet",
+                post(|State(requests): State<Recorder<String>>| async move {
+                    requests.record("addMagnet".to_string());
+                    axum::Json(json!({"id": "ABC", "uri": "https://api.real-debrid.com/ABC"}))
+                }),
+            )
+            .route(
+                "/torrents/info/ABC",
+                get(|State(requests): State<Recorder<String>>| async move {
+                    let count = requests.record("info".to_string());
+                    let status = if count < 3 {
+                        "magnet_conversion"
+                    } else {
+                        "waiting_files_selection"
+                    };
+                    axum::Json(TorrentInfo {
+                        id: "ABC".to_string(),
+                        hash: "DEF".to_string(),
+                        status: status.to_string(),
+                        ..Default::default()
+                    })
+                }),
+            )
+            .route(
+                "/torrents/selectFiles/ABC",
+                post(|State(requests): State<Recorder<String>>| async move {
+                    requests.record("selectFiles".to_string());
+                }),
+            )
+            .with_state(requests.clone());
+        (serve(app).await, requests)
+    }
+
+    #[tokio::test]
+    async fn it_selects_files_once_the_magnet_is_converted() {
+        let (base_url, requests) = start_stub_server().await;
+        let real_debrid = RealDebrid {
+            files_selection_delay: Duration::ZERO,
+            ..RealDebrid::with_base_url("key".to_string(), base_url)
+        };
+
+        let hash = real_debrid
+            .add_torrent(NewTorrent::Magnet("magnet:?xt=urn:btih:DEF".to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(hash, "def");
+        assert_eq!(
+            requests.wait_for(4).await,
+            vec!["addMagnet", "info", "info", "selectFiles"]
+        );
+    }
+
+    #[test]
+    fn it_keeps_only_selected_files() {
+        let torrent = DebridTorrent::from(TorrentInfo {
+            id: "ABC".to_string(),
+            filename: "Show.S01.1080p".to_string(),
+            progress: 100.0,
+            status: "downloaded".to_string(),
+            files: vec![
+                File {
+                    id: 1,
+                    path: "/Show.S01E01.1080p.mkv".to_string(),
+                    bytes: 1000,
+                    selected: 1,
+                },
+                File {
+                    id: 2,
+                    path: "/Sample/sample.mkv".to_string(),
+                    bytes: 10,
+                    selected: 0,
+                },
+            ],
+            ..Default::default()
+        });
+
+        assert_eq!(torrent.progress, 1.0);
+        assert!(torrent.download_finished);
+        assert_eq!(
+            torrent.files,
+            vec![DebridFile {
+                id: 1,
+                name: "Show.S01.1080p/Show.S01E01.1080p.mkv".to_string(),
+                short_name: "Show.S01E01.1080p.mkv".to_string(),
+                size: 1000,
+            }]
+        );
+    }
+
+    #[test]
+    fn it_does_not_nest_single_files() {
+        let torrent = DebridTorrent::from(TorrentInfo {
+            id: "ABC".to_string(),
+            filename: "Heat.1995.1080p.mkv".to_string(),
+            files: vec![File {
+                id: 1,
+                path: "/Heat.1995.1080p.mkv".to_string(),
+                bytes: 1000,
+                selected: 1,
+            }],
+            ..Default::default()
+        });
+
+        assert_eq!(torrent.files[0].name, "Heat.1995.1080p.mkv");
+        assert_eq!(torrent.files[0].short_name, "Heat.1995.1080p.mkv");
+    }
+}
@@ -0,0 +1,37 @@
+use anyhow::Context;
+use std::time::Duration;
+use tracing::warn;
+
+/// Delay before the first retry of a failed request, doubled on each retry.
+pub const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Sends a request, retrying up to `retries` times when it fails.
+pub async fn send_with_retries(
+    request: reqwest::RequestBuilder,
+    retries: u32,
+    retry_delay: Duration,
+) -> anyhow::Result<()> {
+    let mut delay = retry_delay;
+    let mut attempt = 0;
+    loop {
+        let attempt_request = request.try_clone().context("Request can't be retried")?;
+        match send(attempt_request).await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < retries => {
+                warn!("Request failed, retrying: {:?}", e);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+async fn send(request: reqwest::RequestBuilder) -> anyhow::Result<()> {
+    let resp = request.send().await.context("Failed to send request")?;
+    if !resp.status().is_success() {
+        anyhow::bail!("Request failed: {}", resp.status());
+    }
+    Ok(())
+}
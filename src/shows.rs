@@ -1,9 +1,31 @@
-use crate::torbox_client::{ListTorrentsResponse, Torrent};
-use anyhow::Context;
+use crate::anime::parse_anime_episode;
+use crate::debrid::{DebridFileMetadata, DebridTorrent};
+use crate::overrides::{Override, Overrides};
+use crate::quality::Quality;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use tokio::fs;
+use std::ops::RangeInclusive;
+use std::sync::LazyLock;
+use time::{Date, Month};
 use torrent_name_parser::Metadata;
 
+/// Matches specials released without a season, such as `Show.SP01.mkv` or `Show OVA 2.mkv`.
+static SPECIAL_EPISODE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)(?:^|[\s._\-\[(])(?:SP|OVA|OAD|Special)[\s._-]?(?P<episode>\d{1,3})(?:[\s._\-\])]|$)",
+    )
+    .unwrap()
+});
+
+/// Matches the air date of daily shows, such as `Show.2024.03.15.Guest.Name.1080p.mkv`.
+static AIR_DATE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?:^|[\s._\-\[(])(?P<year>(?:19|20)\d{2})[.\-_ ](?P<month>\d{2})[.\-_ ](?P<day>\d{2})(?:[\s._\-\])]|$)",
+    )
+    .unwrap()
+});
+
 #[derive(Debug, Clone)]
 pub struct Show {
     pub title: String,
@@ -14,72 +36,408 @@ pub struct ShowSeason {
     pub number: i32,
     pub episodes: Vec<ShowEpisode>,
 }
-#[derive(Debug, Clone)]
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ShowEpisode {
-    pub number: i32,
-    pub torbox_file_metadata: TorboxFileMetadata,
+    /// Episodes contained in the file, multi-episode files such as `S01E01-E02` span several.
+    /// Date based episodes are numbered after their day of the year.
+    pub numbers: RangeInclusive<i32>,
+    /// Air date of daily shows, they are released without season or episode numbers.
+    #[serde(default)]
+    pub air_date: Option<Date>,
+    /// Not persisted with the episode mapping, it is the key the mapping is stored under.
+    #[serde(skip)]
+    pub debrid_file_metadata: DebridFileMetadata,
+    #[serde(skip)]
     pub size: i64,
+    /// Parsed from the name of the file on every refresh rather than persisted.
+    #[serde(skip)]
+    pub quality: Quality,
     pub file_name: String,
 }
-#[derive(Debug, Clone)]
-pub struct TorboxFileMetadata {
-    pub(crate) file_id: i64,
-    pub(crate) torrent_id: i64,
-}
 
-async fn shows_from_cache() -> anyhow::Result<Vec<Show>> {
-    let file_content = fs::read_to_string("src/torbox_cache.json").await?;
-    let json: ListTorrentsResponse =
-        serde_json::from_str(&file_content).context("failed to parse json")?;
-    parse_shows_from_torrents(json.data)
+impl ShowEpisode {
+    /// Plex style name of the episode, e.g. `Show - s01e01`, `Show - s01e01-e02` or
+    /// `Show - 2024-03-15`.
+    pub fn plex_name(&self, title: &str, season: i32) -> String {
+        let (first, last) = (*self.numbers.start(), *self.numbers.end());
+        match self.air_date {
+            Some(air_date) => format!("{} - {}", title, air_date),
+            None if first == last => format!("{} - s{:02}e{:02}", title, season, first),
+            None => format!("{} - s{:02}e{:02}-e{:02}", title, season, first, last),
+        }
+    }
+
+    /// Name of the file as a Plex version, e.g. `Show - s01e01 - [2160p HDR].mkv`.
+    pub fn version_file_name(&self, title: &str, season: i32, label: &str) -> String {
+        let name = format!("{} - [{}]", self.plex_name(title, season), label);
+        match self.extension() {
+            Some(extension) => format!("{}.{}", name, extension),
+            None => name,
+        }
+    }
+
+    fn extension(&self) -> Option<&str> {
+        self.file_name
+            .rsplit_once('.')
+            .map(|(_, extension)| extension)
+    }
 }
 
-pub fn parse_shows_from_torrents(torrents: Vec<Torrent>) -> anyhow::Result<Vec<Show>> {
-    let mut shows = HashMap::new();
+/// Finds the episodes of shows in the torrents, overrides are applied before grouping them.
+///
+/// Files without a season of their own are specials or daily episodes when named so, otherwise
+/// they inherit the season of their torrent, as in season packs such as `Show.S03.1080p/E01.mkv`.
+/// Daily episodes go in the season of their air year.
+pub fn parse_shows_from_torrents(
+    torrents: Vec<DebridTorrent>,
+    overrides: &Overrides,
+) -> anyhow::Result<Vec<Show>> {
+    let mut episodes = vec![];
 
-    for torrent in torrents {
-        for file in torrent.files {
-            if let Ok(metadata) = Metadata::from(&file.name) {
-                if !metadata.is_show() {
-                    continue;
-                }
+    for torrent in &torrents {
+        let season_pack = torrent
+            .name
+            .as_deref()
+            .filter(|name| Metadata::from(name).is_ok_and(|metadata| metadata.is_show()));
 
-                let title = metadata.title();
-                let season_number = match metadata.season() {
-                    Some(season) => season,
-                    None => continue,
-                };
+        for file in &torrent.files {
+            let file_override = overrides.find(torrent, file);
+            if file_override.is_some_and(|file_override| file_override.ignore) {
+                continue;
+            }
+            let forced_episode = file_override.is_some_and(Override::is_episode);
 
-                let episode_numbers = metadata.episodes();
-                if episode_numbers.len() != 1 {
-                    continue;
+            // Fansub releases are handled by the anime library
+            if !forced_episode && parse_anime_episode(&file.short_name).is_some() {
+                continue;
+            }
+
+            let mut metadata = Metadata::from(&file.name).ok();
+            let special = match metadata.as_ref().and_then(Metadata::season) {
+                Some(_) => None,
+                None => parse_special(&file.short_name),
+            };
+            let air_date = match metadata.as_ref().and_then(Metadata::season) {
+                Some(_) => None,
+                None if forced_episode || special.is_some() => None,
+                None => parse_air_date(&file.short_name),
+            };
+            // Files of season packs such as `Show.S03.1080p` named `E01.mkv` take the title and
+            // season of their torrent
+            if let Some(season_pack) = season_pack
+                && special.is_none()
+                && air_date.is_none()
+                && !metadata.as_ref().is_some_and(Metadata::is_show)
+            {
+                metadata = Metadata::from(&format!("{}/{}", season_pack, file.short_name))
+                    .ok()
+                    .or(metadata);
+            }
+            if !forced_episode
+                && special.is_none()
+                && air_date.is_none()
+                && !metadata.as_ref().is_some_and(Metadata::is_show)
+            {
+                continue;
+            }
+
+            let parsed_title = match (&special, &air_date) {
+                (Some((title, _)), _) | (_, Some((title, _))) if !title.is_empty() => {
+                    Some(title.clone())
                 }
+                (Some(_), _) => season_pack
+                    .and_then(|name| Metadata::from(name).ok())
+                    .map(|metadata| metadata.title().to_string()),
+                _ => metadata
+                    .as_ref()
+                    .map(|metadata| metadata.title().to_string()),
+            };
+            let Some(title) = file_override
+                .and_then(|file_override| file_override.title.clone())
+                .or(parsed_title)
+                .filter(|title| !title.is_empty())
+            else {
+                continue;
+            };
+            let Some(season_number) = file_override
+                .and_then(|file_override| file_override.season)
+                .or_else(|| metadata.as_ref().and_then(Metadata::season))
+                .or(special.as_ref().map(|_| 0))
+                .or(air_date.as_ref().map(|(_, date)| date.year()))
+            else {
+                continue;
+            };
 
-                let show = shows.entry(title.to_string()).or_insert_with(|| Show {
-                    title: title.to_string(),
-                    seasons: HashMap::new(),
-                });
-
-                let season = show
-                    .seasons
-                    .entry(season_number)
-                    .or_insert_with(|| ShowSeason {
-                        number: season_number,
-                        episodes: vec![],
-                    });
-
-                season.episodes.push(ShowEpisode {
-                    number: episode_numbers[0],
-                    size: file.size,
-                    file_name: file.short_name.clone(),
-                    torbox_file_metadata: TorboxFileMetadata {
-                        torrent_id: torrent.id,
-                        file_id: file.id,
+            let episode_numbers =
+                match file_override.and_then(|file_override| file_override.episode) {
+                    Some(episode_number) => vec![episode_number],
+                    None => match (&special, &air_date) {
+                        (Some((_, episode_number)), _) => vec![*episode_number],
+                        (_, Some((_, date))) => vec![date.ordinal() as i32],
+                        _ => metadata
+                            .as_ref()
+                            .map(|metadata| metadata.episodes().clone())
+                            .unwrap_or_default(),
                     },
-                });
+                };
+            let (Some(&first), Some(&last)) =
+                (episode_numbers.iter().min(), episode_numbers.iter().max())
+            else {
+                continue;
+            };
+
+            let mut episode = ShowEpisode {
+                numbers: first..=last,
+                air_date: air_date.map(|(_, date)| date),
+                size: file.size,
+                quality: Quality::parse(&file.name),
+                file_name: file.short_name.clone(),
+                debrid_file_metadata: DebridFileMetadata {
+                    account: torrent.account.clone(),
+                    torrent_id: torrent.id.clone(),
+                    file_id: file.id,
+                },
+            };
+            // Plex only picks daily episodes and whole ranges up with its own naming scheme
+            if first != last || episode.air_date.is_some() {
+                let Some(extension) = episode.extension() else {
+                    continue;
+                };
+                episode.file_name =
+                    format!("{}.{}", episode.plex_name(&title, season_number), extension);
             }
+
+            episodes.push((title, season_number, episode));
+        }
+    }
+
+    Ok(group_episodes(episodes))
+}
+
+/// Extracts the title and episode number of a special released without a season.
+fn parse_special(file_name: &str) -> Option<(String, i32)> {
+    let captures = SPECIAL_EPISODE.captures(file_name)?;
+    let title = file_name[..captures.get(0)?.start()]
+        .replace(['.', '_'], " ")
+        .trim()
+        .to_string();
+    Some((title, captures["episode"].parse().ok()?))
+}
+
+/// Extracts the title and air date of a daily episode.
+pub fn parse_air_date(file_name: &str) -> Option<(String, Date)> {
+    let captures = AIR_DATE.captures(file_name)?;
+    let title = file_name[..captures.get(0)?.start()]
+        .replace(['.', '_'], " ")
+        .trim()
+        .to_string();
+    let month = Month::try_from(captures["month"].parse::<u8>().ok()?).ok()?;
+    let date = Date::from_calendar_date(
+        captures["year"].parse().ok()?,
+        month,
+        captures["day"].parse().ok()?,
+    )
+    .ok()?;
+    Some((title, date))
+}
+
+/// Groups `(show title, season number, episode)` entries into shows.
+pub fn group_episodes(episodes: impl IntoIterator<Item = (String, i32, ShowEpisode)>) -> Vec<Show> {
+    let mut shows = HashMap::new();
+
+    for (title, season_number, episode) in episodes {
+        let show = shows.entry(title.clone()).or_insert_with(|| Show {
+            title,
+            seasons: HashMap::new(),
+        });
+
+        let season = show
+            .seasons
+            .entry(season_number)
+            .or_insert_with(|| ShowSeason {
+                number: season_number,
+                episodes: vec![],
+            });
+
+        season.episodes.push(episode);
+    }
+
+    // Keep episodes in airing order so the tree is built the same way on every refresh
+    for show in shows.values_mut() {
+        for season in show.seasons.values_mut() {
+            season
+                .episodes
+                .sort_by_key(|episode| *episode.numbers.start());
         }
     }
 
-    Ok(shows.into_values().collect())
+    shows.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debrid::DebridFile;
+    use time::macros::date;
+
+    fn torrent(files: &[&str]) -> DebridTorrent {
+        DebridTorrent {
+            id: "1".to_string(),
+            hash: "abcdef".to_string(),
+            files: files
+                .iter()
+                .enumerate()
+                .map(|(id, name)| DebridFile {
+                    id: id as i64,
+                    name: name.to_string(),
+                    short_name: name.to_string(),
+                    size: 1,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_applies_overrides_before_grouping() {
+        let overrides = Overrides::parse(
+            r#"
+            [[override]]
+            file = 'sample'
+            ignore = true
+
+            [[override]]
+            file = '(?i)^the[. ]office'
+            title = "The Office (US)"
+
+            [[override]]
+            file = 'pilot'
+            title = "The Office (US)"
+            season = 1
+            episode = 1
+            "#,
+        )
+        .unwrap();
+
+        let shows = parse_shows_from_torrents(
+            vec![torrent(&[
+                "The.Office.US.S01E02.mkv",
+                "The Office S01E03.mkv",
+                "pilot.mkv",
+                "The.Office.S01E04.sample.mkv",
+            ])],
+            &overrides,
+        )
+        .unwrap();
+
+        assert_eq!(shows.len(), 1);
+        assert_eq!(shows[0].title, "The Office (US)");
+        let episodes = shows[0].seasons[&1]
+            .episodes
+            .iter()
+            .map(|episode| episode.numbers.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(episodes, vec![1..=1, 2..=2, 3..=3]);
+    }
+
+    #[test]
+    fn it_handles_season_packs_and_specials() {
+        let mut season_pack = torrent(&["E01.mkv", "E02.mkv"]);
+        season_pack.name = Some("Show.S03.1080p".to_string());
+        let specials = torrent(&[
+            "Show.S00E01.mkv",
+            "Show.SP02.mkv",
+            "Show.Behind.The.Scenes.mkv",
+        ]);
+
+        let shows =
+            parse_shows_from_torrents(vec![season_pack, specials], &Overrides::default()).unwrap();
+
+        assert_eq!(shows.len(), 1);
+        let mut seasons = shows[0]
+            .seasons
+            .values()
+            .map(|season| {
+                (
+                    season.number,
+                    season
+                        .episodes
+                        .iter()
+                        .map(|episode| episode.numbers.clone())
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+        seasons.sort_by_key(|(number, _)| *number);
+        assert_eq!(
+            seasons,
+            vec![(0, vec![1..=1, 2..=2]), (3, vec![1..=1, 2..=2]),]
+        );
+    }
+
+    #[test]
+    fn it_names_daily_episodes_after_their_air_date() {
+        let shows = parse_shows_from_torrents(
+            vec![torrent(&[
+                "The.Daily.Show.2024.03.15.Guest.Name.1080p.mkv",
+                "The.Daily.Show.2024-01-02.720p.mp4",
+                "Heat.1995.1080p.mkv",
+            ])],
+            &Overrides::default(),
+        )
+        .unwrap();
+
+        assert_eq!(shows.len(), 1);
+        assert_eq!(shows[0].title, "The Daily Show");
+        let episodes = shows[0].seasons[&2024]
+            .episodes
+            .iter()
+            .map(|episode| (episode.air_date, episode.file_name.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            episodes,
+            vec![
+                (
+                    Some(date!(2024 - 01 - 02)),
+                    "The Daily Show - 2024-01-02.mp4"
+                ),
+                (
+                    Some(date!(2024 - 03 - 15)),
+                    "The Daily Show - 2024-03-15.mkv"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_exposes_multi_episode_files() {
+        let shows = parse_shows_from_torrents(
+            vec![torrent(&[
+                "Show.S02E01-E02.1080p.mkv",
+                "Show.S02E03E04.1080p.mkv",
+                "Show.S02E05.1080p.mkv",
+            ])],
+            &Overrides::default(),
+        )
+        .unwrap();
+
+        let episodes = shows[0].seasons[&2]
+            .episodes
+            .iter()
+            .map(|episode| (episode.numbers.clone(), episode.file_name.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            episodes,
+            vec![
+                (1..=2, "Show - s02e01-e02.mkv"),
+                (3..=4, "Show - s02e03-e04.mkv"),
+                (5..=5, "Show.S02E05.1080p.mkv"),
+            ]
+        );
+        assert_eq!(
+            shows[0].seasons[&2].episodes[0].version_file_name("Show", 2, "1080p"),
+            "Show - s02e01-e02 - [1080p].mkv"
+        );
+    }
 }
@@ -0,0 +1,167 @@
+use crate::feeds::{EpisodeSource, MediaKind, Stream, StreamSource};
+use anyhow::Context;
+use async_trait::async_trait;
+use regex::Regex;
+use serde::Deserialize;
+use std::sync::LazyLock;
+
+/// Size shown in the stream titles of Torrentio, e.g. `💾 1.2 GB`.
+static TITLE_SIZE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"💾\s*([0-9.]+)\s*(KB|MB|GB|TB)").unwrap());
+
+/// Reads the streams of a Stremio addon serving torrents, e.g. Torrentio, or the episodes of an
+/// addon serving metadata, e.g. Cinemeta.
+pub struct StremioAddon {
+    /// URL of the addon, without `/manifest.json`.
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl StremioAddon {
+    pub fn new(base_url: String) -> Self {
+        StremioAddon {
+            base_url: base_url
+                .trim_end_matches("/manifest.json")
+                .trim_end_matches('/')
+                .to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl StreamSource for StremioAddon {
+    async fn streams(&self, kind: MediaKind, id: &str) -> anyhow::Result<Vec<Stream>> {
+        let kind = match kind {
+            MediaKind::Movie => "movie",
+            MediaKind::Series => "series",
+        };
+        let url = format!("{}/stream/{}/{}.json", self.base_url, kind, id);
+        let resp = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .context("Failed to send request")?;
+        if !resp.status().is_success() {
+            anyhow::bail!("Request failed: {}", resp.status());
+        }
+        let json = resp
+            .json::<StreamsResponse>()
+            .await
+            .context("Failed to parse json")?;
+        // Streams without an info hash are not torrents
+        Ok(json
+            .streams
+            .into_iter()
+            .filter_map(Stream::from_addon)
+            .collect())
+    }
+}
+
+#[async_trait]
+impl EpisodeSource for StremioAddon {
+    async fn episode_count(&self, imdb_id: &str, season: u32) -> anyhow::Result<Option<u32>> {
+        let url = format!("{}/meta/series/{}.json", self.base_url, imdb_id);
+        let resp = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .context("Failed to send request")?;
+        if !resp.status().is_success() {
+            anyhow::bail!("Request failed: {}", resp.status());
+        }
+        let json = resp
+            .json::<MetaResponse>()
+            .await
+            .context("Failed to parse json")?;
+        Ok(json.meta.and_then(|meta| {
+            meta.videos
+                .iter()
+                .filter(|video| video.season == Some(season))
+                .filter_map(|video| video.episode)
+                .max()
+        }))
+    }
+}
+
+impl Stream {
+    fn from_addon(stream: AddonStream) -> Option<Stream> {
+        let info_hash = stream.info_hash?.to_lowercase();
+        // The first line of the title is the release name, the others are details
+        let title = stream.title.or(stream.description).unwrap_or_default();
+        let release_name = title.lines().next().unwrap_or_default().trim().to_string();
+        let size = stream
+            .behavior_hints
+            .video_size
+            .or_else(|| title_size(&title))
+            .unwrap_or_default();
+        let trackers = stream
+            .sources
+            .iter()
+            .filter_map(|source| source.strip_prefix("tracker:"))
+            .map(String::from)
+            .collect();
+        Some(Stream {
+            info_hash,
+            release_name,
+            file_name: stream.behavior_hints.filename,
+            size,
+            trackers,
+        })
+    }
+}
+
+fn title_size(title: &str) -> Option<i64> {
+    let captures = TITLE_SIZE.captures(title)?;
+    let value = captures[1].parse::<f64>().ok()?;
+    let unit = match &captures[2] {
+        "KB" => 1024.0,
+        "MB" => 1024.0 * 1024.0,
+        "GB" => 1024.0 * 1024.0 * 1024.0,
+        _ => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+    };
+    Some((value * unit) as i64)
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamsResponse {
+    streams: Vec<AddonStream>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AddonStream {
+    info_hash: Option<String>,
+    title: Option<String>,
+    description: Option<String>,
+    #[serde(default)]
+    sources: Vec<String>,
+    #[serde(default)]
+    behavior_hints: BehaviorHints,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BehaviorHints {
+    filename: Option<String>,
+    video_size: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MetaResponse {
+    meta: Option<Meta>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Meta {
+    #[serde(default)]
+    videos: Vec<Video>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Video {
+    season: Option<u32>,
+    episode: Option<u32>,
+}
@@ -0,0 +1,62 @@
+use axum::Router;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// How long a test waits for the requests it expects before failing.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves a stub of another service on a free local port, returns its `http://<address>` URL.
+pub async fn serve(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    format!("http://{}", address)
+}
+
+/// Requests received by a stub, tests wait for the ones they expect rather than sleeping.
+#[derive(Debug)]
+pub struct Recorder<T> {
+    sender: Arc<watch::Sender<Vec<T>>>,
+}
+
+impl<T> Clone for Recorder<T> {
+    fn clone(&self) -> Self {
+        Recorder {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<T> Default for Recorder<T> {
+    fn default() -> Self {
+        Recorder {
+            sender: Arc::new(watch::Sender::new(vec![])),
+        }
+    }
+}
+
+impl<T: Clone> Recorder<T> {
+    /// Records a request, returns how many were received so far, this one included.
+    pub fn record(&self, request: T) -> usize {
+        let mut count = 0;
+        self.sender.send_modify(|requests| {
+            requests.push(request);
+            count = requests.len();
+        });
+        count
+    }
+
+    /// Waits until `count` requests were received, then returns them.
+    pub async fn wait_for(&self, count: usize) -> Vec<T> {
+        let mut receiver = self.sender.subscribe();
+        tokio::time::timeout(
+            WAIT_TIMEOUT,
+            receiver.wait_for(|requests| requests.len() >= count),
+        )
+        .await
+        .unwrap_or_else(|_| panic!("{} requests were expected", count))
+        .unwrap()
+        .clone()
+    }
+}
@@ -1,7 +1,10 @@
+use crate::debrid::{DebridFile, DebridProvider, DebridTorrent, NewTorrent};
 use anyhow::Context;
+use async_trait::async_trait;
 use headers::HeaderValue;
 use moka::future::Cache;
 use reqwest::Response;
+use reqwest::multipart::{Form, Part};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::time::Duration;
@@ -17,9 +20,13 @@ pub struct Torbox {
 
 impl Torbox {
     pub fn new(api_key: String) -> Self {
+        Torbox::with_base_url(api_key, "https://api.torbox.app".to_string())
+    }
+
+    pub fn with_base_url(api_key: String, base_url: String) -> Self {
         Torbox {
             api_key,
-            base_url: "https://api.torbox.app".to_string(),
+            base_url,
             client: reqwest::Client::new(),
             cache: Cache::builder()
                 .time_to_idle(Duration::from_secs(60 * 60 * 3))
@@ -27,7 +34,7 @@ impl Torbox {
         }
     }
 
-    pub async fn list_torrents(&self) -> anyhow::Result<Vec<Torrent>> {
+    async fn my_list(&self) -> anyhow::Result<Vec<Torrent>> {
         let url = format!("{}/v1/api/torrents/mylist", self.base_url);
         let request = self
             .client
@@ -41,17 +48,69 @@ impl Torbox {
             .json::<ListTorrentsResponse>()
             .await
             .context("Failed to parse json")?;
-        let active_torrents: Vec<Torrent> = json
-            .data
+        Ok(json.data)
+    }
+
+    /// Adds a torrent out of a magnet link or the content of a `.torrent` file.
+    pub async fn create_torrent(&self, torrent: NewTorrent) -> anyhow::Result<String> {
+        let form = match torrent {
+            NewTorrent::Magnet(magnet) => Form::new().text("magnet", magnet),
+            NewTorrent::File { name, content } => {
+                Form::new().part("file", Part::bytes(content).file_name(name))
+            }
+        };
+        let url = format!("{}/v1/api/torrents/createtorrent", self.base_url);
+        let request = self
+            .client
+            .request(reqwest::Method::POST, url)
+            .multipart(form)
+            .bearer_auth(&self.api_key);
+        let resp = request.send().await.context("Failed to send request")?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let detail = resp.text().await.unwrap_or_default();
+            anyhow::bail!("Request failed: {} {}", status, detail);
+        }
+        let json = resp
+            .json::<CreateTorrentResponse>()
+            .await
+            .context("Failed to parse json")?;
+        match json.data {
+            Some(created) if json.success => Ok(created.hash.to_lowercase()),
+            _ => anyhow::bail!("Failed to create torrent: {}", json.detail),
+        }
+    }
+}
+
+#[async_trait]
+impl DebridProvider for Torbox {
+    async fn list_torrents(&self) -> anyhow::Result<Vec<DebridTorrent>> {
+        let active_torrents = self
+            .my_list()
+            .await?
             .into_iter()
             .filter(|torrent| torrent.download_present)
+            .map(DebridTorrent::from)
             .collect();
         Ok(active_torrents)
     }
 
-    pub async fn torrent_stream(
+    async fn list_downloads(&self) -> anyhow::Result<Vec<DebridTorrent>> {
+        Ok(self
+            .my_list()
+            .await?
+            .into_iter()
+            .map(DebridTorrent::from)
+            .collect())
+    }
+
+    async fn add_torrent(&self, torrent: NewTorrent) -> anyhow::Result<String> {
+        self.create_torrent(torrent).await
+    }
+
+    async fn torrent_stream(
         &self,
-        torrent_id: i64,
+        torrent_id: &str,
         file_id: i64,
         range_header: Option<HeaderValue>,
     ) -> anyhow::Result<Response> {
@@ -102,13 +161,33 @@ impl Torbox {
     }
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct DownloadLinkResponse {
-    pub success: bool,
-    pub error: Value,
-    pub detail: String,
-    pub data: String,
+impl From<Torrent> for DebridTorrent {
+    fn from(torrent: Torrent) -> Self {
+        DebridTorrent {
+            // Filled in by the account listing the torrent
+            account: String::new(),
+            id: torrent.id.to_string(),
+            hash: torrent.hash,
+            name: torrent.name,
+            size: torrent.size,
+            created_at: torrent.created_at,
+            updated_at: torrent.updated_at,
+            progress: torrent.progress,
+            download_finished: torrent.download_finished,
+            files: torrent.files.into_iter().map(DebridFile::from).collect(),
+        }
+    }
+}
+
+impl From<File> for DebridFile {
+    fn from(file: File) -> Self {
+        DebridFile {
+            id: file.id,
+            name: file.name,
+            short_name: file.short_name,
+            size: file.size,
+        }
+    }
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -205,3 +284,17 @@ struct RequestDownloadLinkResponse {
     pub detail: String,
     pub data: String,
 }
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateTorrentResponse {
+    pub success: bool,
+    pub error: Value,
+    pub detail: String,
+    pub data: Option<CreatedTorrent>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CreatedTorrent {
+    pub hash: String,
+}
@@ -0,0 +1,490 @@
+use crate::fake_file_system::{ChangeKind, FakeFilesystem, Node, PathChange};
+use crate::naming::Naming;
+use crate::retry::{RETRY_DELAY, send_with_retries};
+use anyhow::Context;
+use regex::Regex;
+use reqwest::header::{CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+const EVENTS: [&str; 6] = [
+    "show_added",
+    "episode_added",
+    "movie_added",
+    "torrent_removed",
+    "refresh_failed",
+    "parse_failures",
+];
+const BODY_TOKENS: [&str; 10] = [
+    "event", "message", "path", "show", "episode", "movie", "account", "torrent", "error", "files",
+];
+
+static BODY_TOKEN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{([a-z_]+)\}").unwrap());
+
+/// Something that happened to the library, sent to the webhooks.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LibraryEvent {
+    ShowAdded {
+        show: String,
+        path: String,
+    },
+    EpisodeAdded {
+        show: String,
+        episode: String,
+        path: String,
+    },
+    MovieAdded {
+        movie: String,
+        path: String,
+    },
+    TorrentRemoved {
+        account: String,
+        torrent: String,
+    },
+    RefreshFailed {
+        error: String,
+    },
+    /// Video files of the torrents parsed by a refresh that nothing could be found for.
+    ParseFailures {
+        files: Vec<String>,
+    },
+}
+
+impl LibraryEvent {
+    fn message(&self) -> String {
+        match self {
+            LibraryEvent::ShowAdded { show, .. } => format!("New show: {}", show),
+            LibraryEvent::EpisodeAdded { show, episode, .. } => {
+                format!("New episode of {}: {}", show, episode)
+            }
+            LibraryEvent::MovieAdded { movie, .. } => format!("New movie: {}", movie),
+            LibraryEvent::TorrentRemoved { account, torrent } => {
+                format!("Torrent removed from {}: {}", account, torrent)
+            }
+            LibraryEvent::RefreshFailed { error } => format!("Refresh failed: {}", error),
+            LibraryEvent::ParseFailures { files } => {
+                format!("{} files could not be parsed", files.len())
+            }
+        }
+    }
+
+    /// The JSON payload of the event, its fields along with `event` and `message`.
+    fn payload(&self) -> serde_json::Map<String, Value> {
+        let Ok(Value::Object(mut payload)) = serde_json::to_value(self) else {
+            unreachable!("events are serialized as objects")
+        };
+        payload.insert("message".to_string(), Value::String(self.message()));
+        payload
+    }
+
+    /// Name of the event, as used by the `events` of a webhook.
+    fn name(&self) -> &'static str {
+        match self {
+            LibraryEvent::ShowAdded { .. } => "show_added",
+            LibraryEvent::EpisodeAdded { .. } => "episode_added",
+            LibraryEvent::MovieAdded { .. } => "movie_added",
+            LibraryEvent::TorrentRemoved { .. } => "torrent_removed",
+            LibraryEvent::RefreshFailed { .. } => "refresh_failed",
+            LibraryEvent::ParseFailures { .. } => "parse_failures",
+        }
+    }
+}
+
+/// The events brought by the changes of a refresh, `fake_fs` being the refreshed filesystem.
+pub fn path_events(
+    changes: &[PathChange],
+    fake_fs: &FakeFilesystem,
+    naming: &Naming,
+) -> Vec<LibraryEvent> {
+    let mut events = vec![];
+    for change in changes {
+        if change.kind != ChangeKind::Added {
+            continue;
+        }
+        let Some(components) = change
+            .path
+            .strip_prefix("/")
+            .ok()
+            .and_then(|path| path.iter().map(|c| c.to_str()).collect::<Option<Vec<_>>>())
+        else {
+            continue;
+        };
+        let path = change.path.display().to_string();
+        match components[..] {
+            [root, show, ..] if root == naming.shows_root || root == naming.anime_root => {
+                if components.len() == 2 {
+                    events.push(LibraryEvent::ShowAdded {
+                        show: show.to_string(),
+                        path,
+                    });
+                }
+                for file_path in files(fake_fs, &change.path) {
+                    events.push(LibraryEvent::EpisodeAdded {
+                        show: show.to_string(),
+                        episode: file_name(&file_path),
+                        path: file_path.display().to_string(),
+                    });
+                }
+            }
+            [root, movie] if root == naming.movies_root => {
+                events.push(LibraryEvent::MovieAdded {
+                    movie: movie.to_string(),
+                    path,
+                });
+            }
+            _ => {}
+        }
+    }
+    events
+}
+
+/// The files at or under a path.
+fn files(fake_fs: &FakeFilesystem, path: &Path) -> Vec<PathBuf> {
+    match fake_fs.read_node(path) {
+        Some(Node::File(_)) => vec![path.to_path_buf()],
+        Some(Node::Folder(_)) => fake_fs
+            .read_dir(path)
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|(child_path, _)| files(fake_fs, &child_path))
+            .collect(),
+        None => vec![],
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// An entry of the `[[webhooks]]` array of the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Events sent to the webhook, all of them when empty.
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Template of the request body, the JSON payload of the event by default.
+    pub body: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+impl WebhookConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        reqwest::Url::parse(&self.url).with_context(|| format!("invalid url {}", self.url))?;
+        for event in &self.events {
+            if !EVENTS.contains(&event.as_str()) {
+                anyhow::bail!("unknown event {}, expected one of {:?}", event, EVENTS);
+            }
+        }
+        if let Some(body) = &self.body {
+            BodyTemplate::parse(body)?;
+        }
+        self.header_map()?;
+        Ok(())
+    }
+
+    fn header_map(&self) -> anyhow::Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        for (name, value) in &self.headers {
+            headers.insert(
+                HeaderName::try_from(name).with_context(|| format!("invalid header {}", name))?,
+                HeaderValue::try_from(value)
+                    .with_context(|| format!("invalid value of header {}", name))?,
+            );
+        }
+        Ok(headers)
+    }
+}
+
+/// A request body with `{token}`s replaced by the fields of the event. Values are escaped so they
+/// can be used in JSON strings, lists are joined with commas.
+#[derive(Debug)]
+struct BodyTemplate(String);
+
+impl BodyTemplate {
+    fn parse(source: &str) -> anyhow::Result<BodyTemplate> {
+        for captures in BODY_TOKEN.captures_iter(source) {
+            let token = &captures[1];
+            if !BODY_TOKENS.contains(&token) {
+                anyhow::bail!(
+                    "unknown token `{{{}}}` in body, expected one of {:?}",
+                    token,
+                    BODY_TOKENS
+                );
+            }
+        }
+        Ok(BodyTemplate(source.to_string()))
+    }
+
+    fn render(&self, payload: &serde_json::Map<String, Value>) -> String {
+        BODY_TOKEN
+            .replace_all(&self.0, |captures: &regex::Captures| {
+                let text = match payload.get(&captures[1]) {
+                    Some(Value::String(text)) => text.clone(),
+                    Some(Value::Array(values)) => values
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    Some(value) => value.to_string(),
+                    None => String::new(),
+                };
+                let escaped = Value::String(text).to_string();
+                escaped[1..escaped.len() - 1].to_string()
+            })
+            .to_string()
+    }
+}
+
+/// Sends library events to a webhook. Events that can't be delivered are logged with the
+/// `dead_letter` target.
+#[derive(Clone)]
+pub struct Webhook {
+    sender: mpsc::UnboundedSender<LibraryEvent>,
+}
+
+impl Webhook {
+    pub fn start(config: WebhookConfig) -> anyhow::Result<Webhook> {
+        Webhook::spawn(config, RETRY_DELAY)
+    }
+
+    fn spawn(config: WebhookConfig, retry_delay: Duration) -> anyhow::Result<Webhook> {
+        let task = WebhookTask {
+            body: config
+                .body
+                .as_deref()
+                .map(BodyTemplate::parse)
+                .transpose()?,
+            headers: config.header_map()?,
+            client: reqwest::Client::new(),
+            retry_delay,
+            config,
+        };
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(task.run(receiver));
+        Ok(Webhook { sender })
+    }
+
+    /// Queues the events the webhook subscribed to.
+    pub fn send(&self, events: &[LibraryEvent]) {
+        for event in events {
+            if self.sender.send(event.clone()).is_err() {
+                error!("Webhook deliveries stopped");
+                return;
+            }
+        }
+    }
+}
+
+struct WebhookTask {
+    config: WebhookConfig,
+    body: Option<BodyTemplate>,
+    headers: HeaderMap,
+    client: reqwest::Client,
+    retry_delay: Duration,
+}
+
+impl WebhookTask {
+    async fn run(self, mut receiver: mpsc::UnboundedReceiver<LibraryEvent>) {
+        while let Some(event) = receiver.recv().await {
+            let name = event.name();
+            if !self.config.events.is_empty() && !self.config.events.iter().any(|e| e == name) {
+                continue;
+            }
+
+            let payload = event.payload();
+            let body = match &self.body {
+                Some(body) => body.render(&payload),
+                None => Value::Object(payload).to_string(),
+            };
+            info!(
+                event = name,
+                url = self.config.url,
+                message = "Sending webhook"
+            );
+            let request = self
+                .client
+                .request(reqwest::Method::POST, &self.config.url)
+                .headers(self.headers.clone())
+                .body(body.clone());
+            if let Err(e) = send_with_retries(request, self.config.retries, self.retry_delay).await
+            {
+                error!(
+                    target: "dead_letter",
+                    url = self.config.url,
+                    event = name,
+                    body,
+                    "Failed to deliver webhook: {:?}",
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_file_system::{File, Folder};
+    use crate::stub_server::{Recorder, serve};
+    use axum::Router;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+
+    /// Content type and body of each request.
+    type Requests = Recorder<(String, String)>;
+
+    /// Accepts webhooks after failing the first one, and records the content type and body of
+    /// each request.
+    async fn start_stub_server() -> (String, Requests) {
+        let requests = Requests::default();
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(
+                    |axum::extract::State(requests): axum::extract::State<Requests>,
+                     headers: HeaderMap,
+                     body: String| async move {
+                        let content_type = headers["content-type"].to_str().unwrap().to_string();
+                        if requests.record((content_type, body)) == 1 {
+                            StatusCode::BAD_GATEWAY
+                        } else {
+                            StatusCode::NO_CONTENT
+                        }
+                    },
+                ),
+            )
+            .with_state(requests.clone());
+        (format!("{}/hook", serve(app).await), requests)
+    }
+
+    #[tokio::test]
+    async fn it_sends_templated_webhooks() {
+        let (url, requests) = start_stub_server().await;
+        let webhook = Webhook::spawn(
+            WebhookConfig {
+                url,
+                events: vec!["episode_added".to_string(), "refresh_failed".to_string()],
+                body: Some(r#"{"content": "{message} ({path})"}"#.to_string()),
+                headers: HashMap::new(),
+                retries: 1,
+            },
+            Duration::from_millis(10),
+        )
+        .unwrap();
+
+        webhook.send(&[
+            LibraryEvent::ShowAdded {
+                show: "Show".to_string(),
+                path: "/shows/Show".to_string(),
+            },
+            LibraryEvent::EpisodeAdded {
+                show: "Show".to_string(),
+                episode: "Show \"Pilot\".mkv".to_string(),
+                path: "/shows/Show/Season 1/Show \"Pilot\".mkv".to_string(),
+            },
+            LibraryEvent::RefreshFailed {
+                error: "TorBox is down".to_string(),
+            },
+        ]);
+
+        let episode_added = r#"{"content": "New episode of Show: Show \"Pilot\".mkv (/shows/Show/Season 1/Show \"Pilot\".mkv)"}"#;
+        let requests = requests.wait_for(3).await;
+        let bodies = requests
+            .iter()
+            .map(|(content_type, body)| {
+                assert_eq!(content_type, "application/json");
+                body.as_str()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bodies,
+            vec![
+                episode_added,
+                episode_added,
+                r#"{"content": "Refresh failed: TorBox is down ()"}"#,
+            ]
+        );
+        assert!(BodyTemplate::parse("{show} {season}").is_err());
+    }
+
+    #[test]
+    fn it_finds_added_shows_episodes_and_movies() {
+        let mut fake_fs = FakeFilesystem::new_with_root();
+        for folder in ["/shows", "/shows/Show", "/shows/Show/Season 1", "/movies"] {
+            fake_fs.add_node(
+                Path::new(folder),
+                Node::Folder(Folder {
+                    name: file_name(Path::new(folder)),
+                }),
+            );
+        }
+        for file in [
+            "/shows/Show/Season 1/e01.mkv",
+            "/shows/Show/Season 1/e02.mkv",
+        ] {
+            fake_fs.add_node(
+                Path::new(file),
+                Node::File(File {
+                    name: file_name(Path::new(file)),
+                    size: 1,
+                    download_details: Default::default(),
+                }),
+            );
+        }
+        let change = |path: &str, kind, folder| PathChange {
+            path: PathBuf::from(path),
+            kind,
+            folder,
+        };
+
+        let events = path_events(
+            &[
+                change("/movies/Heat (1995)", ChangeKind::Added, true),
+                change("/movies/Old (1990)", ChangeKind::Removed, true),
+                change("/shows/Show", ChangeKind::Added, true),
+            ],
+            &fake_fs,
+            &Naming::default(),
+        );
+
+        let episode = |episode: &str| LibraryEvent::EpisodeAdded {
+            show: "Show".to_string(),
+            episode: episode.to_string(),
+            path: format!("/shows/Show/Season 1/{}", episode),
+        };
+        assert_eq!(
+            events,
+            vec![
+                LibraryEvent::MovieAdded {
+                    movie: "Heat (1995)".to_string(),
+                    path: "/movies/Heat (1995)".to_string(),
+                },
+                LibraryEvent::ShowAdded {
+                    show: "Show".to_string(),
+                    path: "/shows/Show".to_string(),
+                },
+                episode("e01.mkv"),
+                episode("e02.mkv"),
+            ]
+        );
+    }
+}